@@ -0,0 +1,146 @@
+use crate::data::PAGE_SIZE;
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+// Pages are touched in small batches this often to keep the rate smooth.
+const TICK: Duration = Duration::from_millis(10);
+
+// Share of the touches going to the hot pages of the hot-cold pattern.
+const HOT_ACCESS_RATIO: f64 = 0.9;
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccessPattern {
+    /// Don't touch the allocation while holding it.
+    None,
+    /// Uniformly random pages.
+    Random,
+    /// Rescan the allocation from start to end over and over.
+    Sequential,
+    /// 90% of the touches go to the first --hot-percent of the pages.
+    HotCold,
+    /// Page popularity follows a zipf distribution of exponent
+    /// --zipf-exponent.
+    Zipfian,
+}
+
+/// Reads pages of an allocation during the hold phase so that they are
+/// swapped in while the worker holds them, instead of all at verification.
+pub struct Accessor {
+    pattern: AccessPattern,
+    pages_per_second: u64,
+    hot_fraction: f64,
+    zipf_exponent: f64,
+    next_page: usize,
+    rng: StdRng,
+}
+
+impl Accessor {
+    pub fn new(
+        pattern: AccessPattern,
+        pages_per_second: u64,
+        hot_percent: f64,
+        zipf_exponent: f64,
+    ) -> Accessor {
+        Accessor {
+            pattern,
+            pages_per_second,
+            hot_fraction: hot_percent / 100.0,
+            zipf_exponent,
+            next_page: 0,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Samples a rank in [0, n) from a continuous zipf approximation by
+    /// inverting its CDF, rank 0 being the most popular.
+    fn zipf_rank(&mut self, n: usize) -> usize {
+        let u: f64 = self.rng.gen();
+        let n = n as f64;
+        let s = self.zipf_exponent;
+        let x = if (s - 1.0).abs() < 1e-9 {
+            (u * (n + 1.0).ln()).exp()
+        } else {
+            ((n + 1.0).powf(1.0 - s) * u + (1.0 - u)).powf(1.0 / (1.0 - s))
+        };
+        ((x - 1.0) as usize).min(n as usize - 1)
+    }
+
+    fn next_page(&mut self, pages: usize) -> usize {
+        match self.pattern {
+            AccessPattern::None => 0,
+            AccessPattern::Random => self.rng.gen_range(0..pages),
+            AccessPattern::Sequential => {
+                let page = self.next_page % pages;
+                self.next_page = page + 1;
+                page
+            }
+            AccessPattern::HotCold => {
+                let hot = ((pages as f64 * self.hot_fraction) as usize).clamp(1, pages);
+                if hot == pages || self.rng.gen_bool(HOT_ACCESS_RATIO) {
+                    self.rng.gen_range(0..hot)
+                } else {
+                    self.rng.gen_range(hot..pages)
+                }
+            }
+            AccessPattern::Zipfian => {
+                // Spread the popular ranks over the allocation rather than
+                // packing them at its start.
+                let rank = self.zipf_rank(pages);
+                rank.wrapping_mul(0x9e3779b1) % pages
+            }
+        }
+    }
+
+    /// Keeps touching `slice` for `duration`, returns the number of pages
+    /// touched. Pages are only read so the content is left untouched.
+    pub fn hold(&mut self, slice: &[u8], duration: Duration) -> u64 {
+        let pages = slice.len().div_ceil(PAGE_SIZE);
+        if self.pattern == AccessPattern::None || self.pages_per_second == 0 || pages == 0 {
+            sleep(duration);
+            return 0;
+        }
+        let start = Instant::now();
+        let mut touched: u64 = 0;
+        while start.elapsed() < duration {
+            let due = (start.elapsed().as_secs_f64() * self.pages_per_second as f64) as u64;
+            while touched < due {
+                let offset = self.next_page(pages) * PAGE_SIZE;
+                unsafe { std::ptr::read_volatile(&slice[offset]) };
+                touched += 1;
+            }
+            sleep(TICK.min(duration.saturating_sub(start.elapsed())));
+        }
+        touched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zipf_rank_in_bounds() {
+        for exponent in [0.0, 0.5, 1.0, 1.2, 3.0] {
+            let mut accessor = Accessor::new(AccessPattern::Zipfian, 0, 0.0, exponent);
+            for n in [1, 2, 10, 4096] {
+                let mut counts = vec![0u32; n];
+                for _ in 0..10_000 {
+                    counts[accessor.zipf_rank(n)] += 1;
+                }
+                // Too few samples to rank 4096 pages.
+                if exponent > 0.0 && n <= 10 {
+                    assert_eq!(
+                        counts.iter().max(),
+                        counts.first(),
+                        "rank 0 is the most popular with exponent {} and {} pages",
+                        exponent,
+                        n
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use clap::ValueEnum;
+use libc::{c_void, free, malloc};
+use serde::Serialize;
+use std::ptr::null_mut;
+use std::sync::OnceLock;
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AllocBackend {
+    /// libc malloc, large sizes end up in an anonymous mmap anyway.
+    Malloc,
+    /// Anonymous private mmap, populated lazily on first write.
+    Mmap,
+    /// Anonymous private mmap with MAP_POPULATE.
+    MmapPopulate,
+    /// Anonymous private mmap advised with MADV_NOHUGEPAGE.
+    MmapNohugepage,
+    /// Anonymous private mmap advised with MADV_HUGEPAGE, aligned to the huge
+    /// page size so that it can actually be backed by THPs.
+    MmapHugepage,
+    /// Hugetlb pages with MAP_HUGETLB, the size is rounded up to the huge page
+    /// size and the pages must be reserved (vm.nr_hugepages).
+    Hugetlb,
+}
+
+impl AllocBackend {
+    pub fn name(&self) -> String {
+        self.to_possible_value()
+            .map(|x| x.get_name().to_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// Huge page size as reported by /proc/meminfo, 2MiB if it can't be read.
+pub fn huge_page_size() -> usize {
+    static SIZE: OnceLock<usize> = OnceLock::new();
+    *SIZE.get_or_init(|| {
+        crate::meminfo::parse_meminfo()
+            .ok()
+            .map(|x| x.huge_page_size as usize)
+            .filter(|x| *x > 0)
+            .unwrap_or(2 * 1024 * 1024)
+    })
+}
+
+/// Size actually mapped for a request of `size` bytes.
+fn mapped_size(size: usize, backend: AllocBackend) -> usize {
+    match backend {
+        AllocBackend::Hugetlb => {
+            let hps = huge_page_size();
+            size.div_ceil(hps) * hps
+        }
+        _ => size,
+    }
+}
+
+fn mmap_anonymous(size: usize, extra_flags: libc::c_int) -> *mut c_void {
+    let ptr = unsafe {
+        libc::mmap(
+            null_mut(),
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | extra_flags,
+            -1,
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        null_mut()
+    } else {
+        ptr
+    }
+}
+
+/// Maps `size` bytes aligned to `align`, the excess head and tail are unmapped
+/// so the result can be released with a plain munmap of `size` bytes.
+fn mmap_aligned(size: usize, align: usize) -> *mut c_void {
+    let ptr = mmap_anonymous(size + align, 0);
+    if ptr.is_null() {
+        return ptr;
+    }
+    let start = ptr as usize;
+    let aligned = start.next_multiple_of(align);
+    let head = aligned - start;
+    let tail = align - head;
+    unsafe {
+        if head > 0 {
+            libc::munmap(ptr, head);
+        }
+        if tail > 0 {
+            libc::munmap((aligned + size) as *mut c_void, tail);
+        }
+    }
+    aligned as *mut c_void
+}
+
+fn mmap_advised(size: usize, advice: libc::c_int) -> *mut c_void {
+    let ptr = match advice {
+        libc::MADV_HUGEPAGE => mmap_aligned(size, huge_page_size()),
+        _ => mmap_anonymous(size, 0),
+    };
+    if ptr.is_null() {
+        return ptr;
+    }
+    if unsafe { libc::madvise(ptr, size, advice) } != 0 {
+        unsafe { libc::munmap(ptr, size) };
+        return null_mut();
+    }
+    ptr
+}
+
+/// Allocates `size` bytes with the given backend, returns null on failure.
+pub fn allocate(size: usize, backend: AllocBackend) -> *mut c_void {
+    match backend {
+        AllocBackend::Malloc => unsafe { malloc(size) },
+        AllocBackend::Mmap => mmap_anonymous(size, 0),
+        AllocBackend::MmapPopulate => mmap_anonymous(size, libc::MAP_POPULATE),
+        AllocBackend::MmapNohugepage => mmap_advised(size, libc::MADV_NOHUGEPAGE),
+        AllocBackend::MmapHugepage => mmap_advised(size, libc::MADV_HUGEPAGE),
+        AllocBackend::Hugetlb => mmap_anonymous(mapped_size(size, backend), libc::MAP_HUGETLB),
+    }
+}
+
+/// Releases memory obtained from `allocate` with the same size and backend.
+pub fn release(ptr: *mut c_void, size: usize, backend: AllocBackend) {
+    match backend {
+        AllocBackend::Malloc => unsafe { free(ptr) },
+        _ => unsafe {
+            libc::munmap(ptr, mapped_size(size, backend));
+        },
+    }
+}
@@ -0,0 +1,292 @@
+use crate::psi::{parse_psi_file, render_psi_stats, PsiStats};
+use crate::{fmtb, print_row};
+use anyhow::{bail, Context, Result};
+use byte_unit::Byte;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+// Shown in the dashboard, memory.stat has a few dozen entries and all of them
+// are recorded.
+const SHOWN_STATS: [&str; 8] = [
+    "anon",
+    "file",
+    "kernel",
+    "shmem",
+    "zswap",
+    "zswapped",
+    "pgmajfault",
+    "workingset_refault_anon",
+];
+
+/// Parses a memory limit, "max" or a size such as "512MiB".
+pub fn parse_cgroup_limit(s: &str) -> Result<String> {
+    if s == "max" {
+        return Ok(s.to_owned());
+    }
+    match Byte::from_str(s) {
+        Ok(x) => Ok(x.get_bytes().to_string()),
+        Err(_) => bail!("Expected \"max\" or a size, got {}.", s),
+    }
+}
+
+/// The memory limits of the cgroup, unset ones keep the kernel default.
+#[derive(Clone, Debug, Default)]
+pub struct CgroupLimits {
+    pub max: Option<String>,
+    pub high: Option<String>,
+    pub low: Option<String>,
+    pub swap_max: Option<String>,
+    pub zswap_max: Option<String>,
+}
+
+impl CgroupLimits {
+    fn files(&self) -> Vec<(&str, &String)> {
+        [
+            ("memory.max", &self.max),
+            ("memory.high", &self.high),
+            ("memory.low", &self.low),
+            ("memory.swap.max", &self.swap_max),
+            ("memory.zswap.max", &self.zswap_max),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.as_ref().map(|value| (name, value)))
+        .collect()
+    }
+}
+
+/// Where the cgroup v2 hierarchy is mounted, /sys/fs/cgroup unless the
+/// system still has the v1 controllers there.
+pub fn cgroup2_mount() -> Result<PathBuf> {
+    let txt = std::fs::read_to_string("/proc/self/mountinfo")
+        .context("Could not read /proc/self/mountinfo.")?;
+    txt.lines()
+        .find_map(|line| {
+            // The mount point is the 5th field, the filesystem type the first
+            // one after the " - " separator.
+            let (mount, fs) = line.split_once(" - ")?;
+            match fs.split_whitespace().next() {
+                Some("cgroup2") => mount.split_whitespace().nth(4).map(PathBuf::from),
+                _ => None,
+            }
+        })
+        .context("cgroup v2 is not mounted.")
+}
+
+/// The directory of the cgroup v2 we are in.
+pub fn current_cgroup() -> Result<PathBuf> {
+    let txt = std::fs::read_to_string("/proc/self/cgroup")
+        .context("Could not read /proc/self/cgroup.")?;
+    let Some(path) = txt.lines().find_map(|line| line.strip_prefix("0::")) else {
+        bail!("Not in a cgroup v2.");
+    };
+    let mount = cgroup2_mount()?;
+    Ok(match path.trim_start_matches('/') {
+        "" => mount,
+        path => mount.join(path),
+    })
+}
+
+fn read_file(dir: &Path, name: &str) -> Option<String> {
+    std::fs::read_to_string(dir.join(name)).ok()
+}
+
+fn write_file(dir: &Path, name: &str, value: &str) -> Result<()> {
+    let path = dir.join(name);
+    std::fs::write(&path, value).context(format!(
+        "Could not write \"{}\" to {}.",
+        value,
+        path.display()
+    ))
+}
+
+fn controllers(dir: &Path, name: &str) -> Vec<String> {
+    read_file(dir, name)
+        .map(|txt| txt.split_whitespace().map(|x| x.to_owned()).collect())
+        .unwrap_or_default()
+}
+
+#[derive(Clone, Serialize)]
+pub struct CgroupCounter {
+    pub name: String,
+    pub value: u128,
+}
+
+/// Parses the "<key> <value>" lines of memory.stat and memory.events.
+fn parse_flat_keyed(txt: Option<String>) -> Vec<CgroupCounter> {
+    txt.unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(' ')?;
+            Some(CgroupCounter {
+                name: name.to_owned(),
+                value: value.trim().parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// The memory usage of the mstress cgroup.
+#[derive(Clone, Serialize)]
+pub struct CgroupStats {
+    pub path: String,
+    pub current: u128,
+    pub swap_current: Option<u128>,
+    pub zswap_current: Option<u128>,
+    pub stat: Vec<CgroupCounter>,
+    pub events: Vec<CgroupCounter>,
+    pub pressure: PsiStats,
+}
+
+impl CgroupStats {
+    pub fn get(&self, name: &str) -> Option<u128> {
+        self.stat.iter().find(|c| c.name == name).map(|c| c.value)
+    }
+}
+
+pub fn parse_cgroup(dir: &Path) -> Result<CgroupStats> {
+    let number = |name: &str| read_file(dir, name).and_then(|x| x.trim().parse::<u128>().ok());
+    Ok(CgroupStats {
+        path: dir.to_string_lossy().into_owned(),
+        current: number("memory.current")
+            .context(format!("Could not read memory.current of {}.", dir.display()))?,
+        swap_current: number("memory.swap.current"),
+        zswap_current: number("memory.zswap.current"),
+        stat: parse_flat_keyed(read_file(dir, "memory.stat")),
+        events: parse_flat_keyed(read_file(dir, "memory.events")),
+        pressure: parse_psi_file(&dir.join("memory.pressure"))?,
+    })
+}
+
+pub fn render_cgroup_stats(stats: &CgroupStats) {
+    print_row(&["CGROUP", &stats.path], "<>");
+    print_row(&["current", &fmtb(stats.current)], "<>");
+    if let Some(x) = stats.swap_current {
+        print_row(&["swap current", &fmtb(x)], "<>");
+    }
+    if let Some(x) = stats.zswap_current {
+        print_row(&["zswap current", &fmtb(x)], "<>");
+    }
+    SHOWN_STATS.iter().for_each(|name| {
+        if let Some(value) = stats.get(name) {
+            let value = match *name {
+                "pgmajfault" | "workingset_refault_anon" => value.to_string(),
+                _ => fmtb(value),
+            };
+            print_row(&[name, &value], "<>");
+        }
+    });
+    stats.events.iter().for_each(|event| {
+        print_row(&[&format!("events {}", event.name), &event.value.to_string()], "<>");
+    });
+    render_psi_stats(&stats.pressure);
+}
+
+/// A child cgroup mstress moved itself into, moved back out of and removed
+/// when dropped.
+pub struct Cgroup {
+    dir: PathBuf,
+    parent: PathBuf,
+    // Only a controller we enabled in the parent is disabled again.
+    enabled_memory: bool,
+}
+
+impl Cgroup {
+    /// Creates a cgroup under the current one and moves the whole process
+    /// into it. The memory controller has no threaded mode, so it isn't
+    /// possible to only move the workers.
+    pub fn setup(limits: &CgroupLimits) -> Result<Cgroup> {
+        let parent = current_cgroup()?;
+        if !controllers(&parent, "cgroup.controllers").contains(&"memory".to_owned()) {
+            bail!(
+                "The memory controller is not available in {}, is it bound to cgroup v1?",
+                parent.display()
+            );
+        }
+        let dir = parent.join(format!("mstress-{}", std::process::id()));
+        std::fs::create_dir(&dir).context(format!("Could not create {}.", dir.display()))?;
+        let mut cgroup = Cgroup {
+            dir,
+            parent,
+            enabled_memory: false,
+        };
+        // Dropping it undoes what was done so far if a step fails.
+        cgroup.configure(limits)?;
+        Ok(cgroup)
+    }
+
+    fn configure(&mut self, limits: &CgroupLimits) -> Result<()> {
+        // A cgroup other than the root can't both have processes and enable
+        // controllers for its children, move out before enabling memory.
+        write_file(&self.dir, "cgroup.procs", &std::process::id().to_string())?;
+        if !controllers(&self.parent, "cgroup.subtree_control").contains(&"memory".to_owned()) {
+            write_file(&self.parent, "cgroup.subtree_control", "+memory")
+                .context("Are there other processes in the cgroup?")?;
+            self.enabled_memory = true;
+        }
+        for (name, value) in limits.files() {
+            write_file(&self.dir, name, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Every step is attempted even if an earlier one failed.
+    fn teardown(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        if self.enabled_memory {
+            if let Err(err) = write_file(&self.parent, "cgroup.subtree_control", "-memory") {
+                errors.push(format!("{:#}", err));
+            }
+        }
+        if let Err(err) = write_file(&self.parent, "cgroup.procs", &std::process::id().to_string()) {
+            errors.push(format!("{:#}", err));
+        }
+        if let Err(err) = std::fs::remove_dir(&self.dir) {
+            errors.push(format!(
+                "Could not remove {}, rmdir it once mstress exited: {}",
+                self.dir.display(),
+                err
+            ));
+        }
+        if !errors.is_empty() {
+            bail!(errors.join("\n"));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        if let Err(err) = self.teardown() {
+            println!("Could not tear down the cgroup.\n{}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_limit() {
+        assert_eq!(parse_cgroup_limit("max").unwrap(), "max");
+        assert_eq!(parse_cgroup_limit("512MiB").unwrap(), "536870912");
+        assert_eq!(parse_cgroup_limit("4096").unwrap(), "4096");
+        assert!(parse_cgroup_limit("unlimited").is_err());
+        assert!(parse_cgroup_limit("").is_err());
+    }
+
+    #[test]
+    fn parse_keyed_counters() {
+        let counters = parse_flat_keyed(Some("anon 4096\nfile 0\nbroken\nzswapped x\n".to_owned()));
+        let counters: Vec<(&str, u128)> = counters
+            .iter()
+            .map(|c| (c.name.as_str(), c.value))
+            .collect();
+        assert_eq!(counters, vec![("anon", 4096), ("file", 0)]);
+        assert!(parse_flat_keyed(None).is_empty());
+    }
+}
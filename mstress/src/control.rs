@@ -0,0 +1,173 @@
+use crate::data::{parse_data_mix, DataMix};
+use anyhow::{bail, Context, Result};
+use byte_unit::Byte;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A change requested over the control socket.
+pub enum ControlCommand {
+    Threads(u16),
+    ThreadBytes(usize),
+    HoldTimeMs(u64),
+    DataProfile(DataMix),
+    Pause,
+    Resume,
+    Stats,
+}
+
+/// Parses a request such as "threads 4", "thread-bytes 64MiB",
+/// "hold-time-ms 500", "data-profile zero:50,random:50", "pause", "resume"
+/// or "stats".
+pub fn parse_command(request: &str) -> Result<ControlCommand> {
+    let (name, value) = match request.trim().split_once(char::is_whitespace) {
+        Some((name, value)) => (name, value.trim()),
+        None => (request.trim(), ""),
+    };
+    let command = match name {
+        "threads" => ControlCommand::Threads(
+            value
+                .parse::<u16>()
+                .context(format!("Invalid thread count {}.", value))?,
+        ),
+        "thread-bytes" => ControlCommand::ThreadBytes(
+            Byte::from_str(value)
+                .map_err(|err| anyhow::anyhow!("Invalid size {}, {}.", value, err))?
+                .get_bytes() as usize,
+        ),
+        "hold-time-ms" => ControlCommand::HoldTimeMs(
+            value
+                .parse::<u64>()
+                .context(format!("Invalid hold time {}.", value))?,
+        ),
+        "data-profile" => ControlCommand::DataProfile(parse_data_mix(value)?),
+        "pause" => ControlCommand::Pause,
+        "resume" => ControlCommand::Resume,
+        "stats" => ControlCommand::Stats,
+        _ => bail!(
+            "Unknown request {}, expected threads, thread-bytes, hold-time-ms, data-profile, pause, resume or stats.",
+            name
+        ),
+    };
+    Ok(command)
+}
+
+/// A request read from a client, answered with `reply`.
+pub struct ControlRequest {
+    stream: UnixStream,
+    pub line: String,
+}
+
+impl ControlRequest {
+    pub fn reply(mut self, response: &str) {
+        // The client might be gone already, nothing to do about it.
+        let _ = writeln!(self.stream, "{}", response);
+    }
+}
+
+/// Listens for requests on a Unix socket, the socket file is removed on drop.
+pub struct ControlServer {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ControlServer {
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<ControlServer> {
+        let path = path.as_ref().to_path_buf();
+        // A socket left behind by a previous run would make bind fail.
+        if path.exists() {
+            std::fs::remove_file(&path)
+                .context(format!("Could not remove {}.", path.display()))?;
+        }
+        let listener =
+            UnixListener::bind(&path).context(format!("Could not bind {}.", path.display()))?;
+        listener.set_nonblocking(true)?;
+        Ok(ControlServer { listener, path })
+    }
+
+    /// Returns the request of the next client if one is waiting.
+    pub fn accept(&self) -> Result<Option<ControlRequest>> {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        Ok(Some(ControlRequest {
+            stream,
+            line: line.trim().to_owned(),
+        }))
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Sends `request` to the control socket at `path` and returns the response.
+pub fn send_request(path: &str, request: &str) -> Result<String> {
+    let mut stream =
+        UnixStream::connect(path).context(format!("Could not connect to {}.", path))?;
+    writeln!(stream, "{}", request)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert!(matches!(
+            parse_command("threads 4"),
+            Ok(ControlCommand::Threads(4))
+        ));
+        assert!(matches!(
+            parse_command("thread-bytes 64MiB"),
+            Ok(ControlCommand::ThreadBytes(67108864))
+        ));
+        assert!(matches!(
+            parse_command("hold-time-ms  500\n"),
+            Ok(ControlCommand::HoldTimeMs(500))
+        ));
+        assert!(matches!(
+            parse_command("data-profile zero:50, random:50"),
+            Ok(ControlCommand::DataProfile(_))
+        ));
+        assert!(matches!(
+            parse_command(" pause "),
+            Ok(ControlCommand::Pause)
+        ));
+        assert!(matches!(
+            parse_command("resume"),
+            Ok(ControlCommand::Resume)
+        ));
+        assert!(matches!(
+            parse_command("stats\n"),
+            Ok(ControlCommand::Stats)
+        ));
+    }
+
+    #[test]
+    fn parse_command_errors() {
+        for request in [
+            "",
+            "threads",
+            "threads -1",
+            "thread-bytes lots",
+            "hold-time-ms 1.5",
+            "data-profile zero:10",
+            "jump",
+        ] {
+            assert!(parse_command(request).is_err(), "{}", request);
+        }
+    }
+}
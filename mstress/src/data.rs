@@ -0,0 +1,191 @@
+use anyhow::{bail, Context, Result};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+pub const PAGE_SIZE: usize = 4096;
+
+const WORDS: &str = "the page memory swap kernel compress pool zswap of and to in is that for it \
+    with as was on reclaim writeback cache fault anon file lru folio entry tree offset index";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DataProfile {
+    /// All zeros, stored by zswap as a same-filled page.
+    Zero,
+    /// Every word of the page has the same non-zero value.
+    SameFilled,
+    /// Fully random, not compressible.
+    Random,
+    /// Space separated words, compresses like plain text.
+    Text,
+    /// A random prefix of `random_len` bytes followed by zeros, compresses to
+    /// roughly PAGE_SIZE / random_len.
+    Ratio { random_len: usize },
+}
+
+/// The pages of an allocation are split between profiles by percentage, the
+/// profile of a page only depends on its index so that the verifier can tell
+/// what each page is supposed to contain.
+#[derive(Clone, Debug)]
+pub struct DataMix {
+    // Profiles with their cumulative percentage, the last one is always 100.
+    profiles: Vec<(DataProfile, u8)>,
+}
+
+fn parse_profile(name: &str) -> Result<DataProfile> {
+    if let Some(ratio) = name.strip_prefix("ratio=") {
+        let ratio = ratio
+            .parse::<f64>()
+            .context(format!("Invalid compression ratio {}.", ratio))?;
+        if ratio < 1.0 {
+            bail!("Compression ratio must be at least 1.");
+        }
+        let random_len = (PAGE_SIZE as f64 / ratio) as usize;
+        return Ok(DataProfile::Ratio { random_len });
+    }
+    Ok(match name {
+        "zero" => DataProfile::Zero,
+        "same" | "same-filled" => DataProfile::SameFilled,
+        "random" => DataProfile::Random,
+        "text" => DataProfile::Text,
+        _ => bail!(
+            "Unknown data profile {}, expected zero, same-filled, random, text or ratio=<n>.",
+            name
+        ),
+    })
+}
+
+/// Parses a profile mix such as "zero:20,same-filled:10,text:40,ratio=3:30",
+/// a single profile without percentage is used for every page.
+pub fn parse_data_mix(s: &str) -> Result<DataMix> {
+    let mut profiles = Vec::new();
+    let mut total: u32 = 0;
+    let entries: Vec<&str> = s.split(',').map(|x| x.trim()).collect();
+    for entry in entries.iter() {
+        let (name, percent) = match entry.rsplit_once(':') {
+            Some((name, percent)) => (
+                name,
+                percent
+                    .parse::<u8>()
+                    .context(format!("Invalid percent in {}.", entry))?,
+            ),
+            None if entries.len() == 1 => (*entry, 100),
+            None => bail!("Missing percent for {} in data profile mix.", entry),
+        };
+        total += percent as u32;
+        if total > 100 {
+            bail!("Data profile percentages must add up to 100.");
+        }
+        profiles.push((parse_profile(name)?, total as u8));
+    }
+    if total != 100 {
+        bail!(
+            "Data profile percentages must add up to 100, got {}.",
+            total
+        );
+    }
+    Ok(DataMix { profiles })
+}
+
+impl DataMix {
+    /// The historical behavior, a random prefix of every page.
+    pub fn random_prefix(random_len: usize) -> DataMix {
+        DataMix {
+            profiles: vec![(DataProfile::Ratio { random_len }, 100)],
+        }
+    }
+
+    pub fn profile_for_page(&self, page_index: usize) -> DataProfile {
+        if self.profiles.len() == 1 {
+            return self.profiles[0].0;
+        }
+        // Cheap integer hash so that profiles are spread over the allocation
+        // instead of being laid out in contiguous chunks.
+        let h = (page_index as u64)
+            .wrapping_mul(0x9e3779b97f4a7c15)
+            .rotate_left(31);
+        let bucket = (h % 100) as u8;
+        self.profiles
+            .iter()
+            .find(|(_, cumulative)| bucket < *cumulative)
+            .map(|(profile, _)| *profile)
+            .unwrap_or(self.profiles[self.profiles.len() - 1].0)
+    }
+
+    /// For pages that must be filled with a single byte (zero and same-filled)
+    /// returns that byte. Markers are not written on those pages, the whole
+    /// page is checked instead.
+    pub fn fill_byte(&self, page_index: usize) -> Option<u8> {
+        match self.profile_for_page(page_index) {
+            DataProfile::Zero => Some(0),
+            DataProfile::SameFilled => Some((page_index as u8).wrapping_mul(31) | 1),
+            _ => None,
+        }
+    }
+
+    pub fn fill_page<R: Rng>(&self, page_index: usize, page: &mut [u8], rng: &mut R) {
+        match self.profile_for_page(page_index) {
+            DataProfile::Zero | DataProfile::SameFilled => {
+                page.fill(self.fill_byte(page_index).unwrap_or(0));
+            }
+            DataProfile::Random => rng.fill(page),
+            DataProfile::Text => {
+                let words: Vec<&str> = WORDS.split_whitespace().collect();
+                let mut i = 0;
+                while i < page.len() {
+                    let word = words.choose(rng).unwrap_or(&"page").as_bytes();
+                    let n = word.len().min(page.len() - i);
+                    page[i..i + n].copy_from_slice(&word[..n]);
+                    i += n;
+                    if i < page.len() {
+                        page[i] = if rng.gen_ratio(1, 12) { b'\n' } else { b' ' };
+                        i += 1;
+                    }
+                }
+            }
+            DataProfile::Ratio { random_len } => {
+                // Zero-filled even without a random prefix, malloc can hand
+                // back memory holding old data.
+                let random_len = random_len.min(page.len());
+                rng.fill(&mut page[..random_len]);
+                page[random_len..].fill(0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mix() {
+        let mix = parse_data_mix("zero:20, same-filled:10,random:40,ratio=4:30").unwrap();
+        assert_eq!(
+            mix.profiles,
+            vec![
+                (DataProfile::Zero, 20),
+                (DataProfile::SameFilled, 30),
+                (DataProfile::Random, 70),
+                (DataProfile::Ratio { random_len: 1024 }, 100),
+            ]
+        );
+        let mix = parse_data_mix("text").unwrap();
+        assert_eq!(mix.profiles, vec![(DataProfile::Text, 100)]);
+        assert_eq!(mix.profile_for_page(12), DataProfile::Text);
+    }
+
+    #[test]
+    fn parse_mix_errors() {
+        for mix in [
+            "zero:20,random:70",
+            "zero:60,random:60",
+            "zero,random:50",
+            "zero:abc",
+            "ratio=0.5",
+            "ratio=x",
+            "garbage",
+        ] {
+            assert!(parse_data_mix(mix).is_err(), "{}", mix);
+        }
+    }
+}
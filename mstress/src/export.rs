@@ -0,0 +1,166 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    /// One row per value: time_ms,elapsed_ms,kind,key,value.
+    Csv,
+    /// One JSON object per record.
+    Jsonl,
+}
+
+/// Writes every record of a run to a file so it can be plotted afterwards.
+pub struct Exporter {
+    writer: BufWriter<File>,
+    format: OutputFormat,
+    start_time: Instant,
+}
+
+/// Flattens nested objects into dotted keys, e.g. {"psi": {"some": {"avg10"}}}
+/// becomes "psi.some.avg10". Arrays of objects with a "name" field (the zswap
+/// and vmstat counters) use the name as key.
+fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, Value)>) {
+    let join = |key: &str| match prefix {
+        "" => key.to_owned(),
+        _ => format!("{}.{}", prefix, key),
+    };
+    match value {
+        Value::Object(map) => map.iter().for_each(|(k, v)| flatten(&join(k), v, out)),
+        Value::Array(items) => items.iter().enumerate().for_each(|(i, item)| {
+            let key = match item.get("name").and_then(|x| x.as_str()) {
+                Some(name) => name.to_owned(),
+                None => i.to_string(),
+            };
+            match item {
+                Value::Object(map) => map
+                    .iter()
+                    .filter(|(k, _)| *k != "name")
+                    .for_each(|(k, v)| flatten(&join(&format!("{}.{}", key, k)), v, out)),
+                _ => flatten(&join(&key), item, out),
+            }
+        }),
+        _ => out.push((prefix.to_owned(), value.clone())),
+    }
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+impl Exporter {
+    pub fn create<P: AsRef<Path>>(path: P, format: OutputFormat) -> Result<Exporter> {
+        let file = File::create(path.as_ref()).context(format!(
+            "Could not create output file {}.",
+            path.as_ref().display()
+        ))?;
+        let mut writer = BufWriter::new(file);
+        if format == OutputFormat::Csv {
+            writeln!(writer, "time_ms,elapsed_ms,kind,key,value")?;
+        }
+        Ok(Exporter {
+            writer,
+            format,
+            start_time: Instant::now(),
+        })
+    }
+
+    /// Records an event of the given kind, `data` can be any serializable
+    /// value, typically a stats struct or a small json! object.
+    pub fn record<T: Serialize>(&mut self, kind: &str, data: &T) -> Result<()> {
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis())
+            .unwrap_or(0);
+        let elapsed_ms = self.start_time.elapsed().as_millis();
+        let data = serde_json::to_value(data)?;
+        match self.format {
+            OutputFormat::Jsonl => {
+                let mut obj = Map::new();
+                obj.insert("time_ms".to_owned(), json!(time_ms as u64));
+                obj.insert("elapsed_ms".to_owned(), json!(elapsed_ms as u64));
+                obj.insert("kind".to_owned(), json!(kind));
+                obj.insert("data".to_owned(), data);
+                writeln!(self.writer, "{}", Value::Object(obj))?;
+            }
+            OutputFormat::Csv => {
+                let mut fields = Vec::new();
+                flatten("", &data, &mut fields);
+                for (key, value) in fields {
+                    let value = match value {
+                        Value::Null => "".to_owned(),
+                        Value::String(s) => s,
+                        x => x.to_string(),
+                    };
+                    writeln!(
+                        self.writer,
+                        "{},{},{},{},{}",
+                        time_ms,
+                        elapsed_ms,
+                        kind,
+                        csv_escape(&key),
+                        csv_escape(&value)
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn flatten_nested() {
+        let mut out = Vec::new();
+        flatten(
+            "",
+            &json!({
+                "psi": {"some": {"avg10": 1.5}},
+                "zswap": {"counters": [{"name": "pool_total_size", "total": 4096, "delta": 0}]},
+                "swaps": [{"size": 1}, 2],
+                "paused": false,
+            }),
+            &mut out,
+        );
+        assert_eq!(
+            out,
+            vec![
+                ("paused".to_owned(), json!(false)),
+                ("psi.some.avg10".to_owned(), json!(1.5)),
+                ("swaps.0.size".to_owned(), json!(1)),
+                ("swaps.1".to_owned(), json!(2)),
+                ("zswap.counters.pool_total_size.delta".to_owned(), json!(0)),
+                (
+                    "zswap.counters.pool_total_size.total".to_owned(),
+                    json!(4096)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn escape_csv() {
+        assert_eq!(csv_escape("plain"), "plain");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_escape("two\nlines"), "\"two\nlines\"");
+    }
+}
@@ -0,0 +1,147 @@
+use crate::zswap::{read_parameter, write_parameter};
+use anyhow::{bail, Context, Result};
+
+/// The zswap parameters that can be flipped during a run or swept.
+const FLIPPABLE: [&str; 6] = [
+    "max_pool_percent",
+    "enabled",
+    "compressor",
+    "zpool",
+    "accept_threshold_percent",
+    "shrinker_enabled",
+];
+
+/// A zswap parameter and the values it cycles through.
+#[derive(Clone, Debug)]
+pub struct Flip {
+    pub parameter: String,
+    pub values: Vec<String>,
+}
+
+/// Parses "<parameter>=<value>[,<value>...]" with at least `min_values`
+/// values.
+pub fn parse_parameter_values(s: &str, min_values: usize) -> Result<Flip> {
+    let Some((parameter, values)) = s.split_once('=') else {
+        bail!("Expected <parameter>=<value>,<value>, got {}.", s);
+    };
+    let parameter = parameter.trim();
+    if !FLIPPABLE.contains(&parameter) {
+        bail!(
+            "Unknown zswap parameter {}, expected one of {}.",
+            parameter,
+            FLIPPABLE.join(", ")
+        );
+    }
+    let values: Vec<String> = values
+        .split(',')
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+        .collect();
+    if values.len() < min_values {
+        bail!("Parameter {} needs at least {} values.", parameter, min_values);
+    }
+    Ok(Flip {
+        parameter: parameter.to_owned(),
+        values,
+    })
+}
+
+/// Parses "<parameter>=<value>,<value>[,...]", e.g. "compressor=lzo,zstd".
+pub fn parse_flip(s: &str) -> Result<Flip> {
+    parse_parameter_values(s, 2)
+}
+
+impl Flip {
+    /// Flips max_pool_percent between 0 and its current value.
+    pub fn max_pool_percent() -> Result<Flip> {
+        let current = read_parameter("max_pool_percent")?;
+        if current == "0" {
+            bail!("max_pool_percent is already 0, use --zswap-flip max_pool_percent=0,<percent>.");
+        }
+        Ok(Flip {
+            parameter: "max_pool_percent".to_owned(),
+            values: vec!["0".to_owned(), current],
+        })
+    }
+}
+
+/// The outcome of one parameter write.
+pub struct FlipResult {
+    pub parameter: String,
+    pub value: String,
+    pub error: Option<String>,
+}
+
+/// Cycles zswap module parameters through their values, every call to `flip`
+/// moves each parameter to its next value. The values found at creation are
+/// put back by `restore`.
+pub struct ZswapFlipper {
+    flips: Vec<Flip>,
+    next: usize,
+    original: Vec<(String, String)>,
+}
+
+impl ZswapFlipper {
+    pub fn new(flips: Vec<Flip>) -> Result<ZswapFlipper> {
+        let original = flips
+            .iter()
+            .map(|flip| {
+                read_parameter(&flip.parameter)
+                    .map(|value| (flip.parameter.clone(), value))
+                    .context("Is zswap built in or loaded?")
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ZswapFlipper {
+            flips,
+            next: 0,
+            original,
+        })
+    }
+
+    pub fn flip(&mut self) -> Vec<FlipResult> {
+        let next = self.next;
+        self.next += 1;
+        self.flips
+            .iter()
+            .map(|flip| {
+                let value = flip.values[next % flip.values.len()].clone();
+                let error = write_parameter(&flip.parameter, &value)
+                    .err()
+                    .map(|err| format!("{:#}", err));
+                FlipResult {
+                    parameter: flip.parameter.clone(),
+                    value,
+                    error,
+                }
+            })
+            .collect()
+    }
+
+    pub fn restore(&self) -> Result<()> {
+        self.original
+            .iter()
+            .try_for_each(|(parameter, value)| write_parameter(parameter, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_values() {
+        let flip = parse_parameter_values(" compressor = lzo, zstd,,", 2).unwrap();
+        assert_eq!(flip.parameter, "compressor");
+        assert_eq!(flip.values, vec!["lzo", "zstd"]);
+        let flip = parse_parameter_values("max_pool_percent=0", 1).unwrap();
+        assert_eq!(flip.values, vec!["0"]);
+    }
+
+    #[test]
+    fn parse_values_errors() {
+        assert!(parse_parameter_values("compressor", 1).is_err());
+        assert!(parse_parameter_values("compressor=lzo", 2).is_err());
+        assert!(parse_parameter_values("compressor=", 1).is_err());
+        assert!(parse_parameter_values("swappiness=10,60", 2).is_err());
+    }
+}
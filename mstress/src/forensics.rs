@@ -0,0 +1,251 @@
+use crate::data::{DataMix, PAGE_SIZE};
+use crate::verify::VerifyFailure;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// At most this many bad pages get a full hexdiff, the others are only listed.
+const MAX_DETAILED_PAGES: usize = 16;
+
+const PM_PFN_MASK: u64 = (1 << 55) - 1;
+const PM_SOFT_DIRTY: u64 = 1 << 55;
+const PM_EXCLUSIVE: u64 = 1 << 56;
+const PM_FILE: u64 = 1 << 61;
+const PM_SWAP: u64 = 1 << 62;
+const PM_PRESENT: u64 = 1 << 63;
+
+const KPF_SWAPCACHE: u32 = 13;
+
+// Bit names from include/uapi/linux/kernel-page-flags.h.
+const KPAGEFLAGS: [&str; 27] = [
+    "LOCKED",
+    "ERROR",
+    "REFERENCED",
+    "UPTODATE",
+    "DIRTY",
+    "LRU",
+    "ACTIVE",
+    "SLAB",
+    "WRITEBACK",
+    "RECLAIM",
+    "BUDDY",
+    "MMAP",
+    "ANON",
+    "SWAPCACHE",
+    "SWAPBACKED",
+    "COMPOUND_HEAD",
+    "COMPOUND_TAIL",
+    "HUGE",
+    "UNEVICTABLE",
+    "HWPOISON",
+    "NOPAGE",
+    "KSM",
+    "THP",
+    "OFFLINE",
+    "ZERO_PAGE",
+    "IDLE",
+    "PGTABLE",
+];
+
+fn read_u64_at(path: &str, index: u64) -> Result<u64> {
+    let mut file = File::open(path).context(format!("Could not open {}.", path))?;
+    file.seek(SeekFrom::Start(index * 8))?;
+    let mut buf = [0u8; 8];
+    file.read_exact(&mut buf)
+        .context(format!("Could not read {}.", path))?;
+    Ok(u64::from_ne_bytes(buf))
+}
+
+fn decode_kpageflags(flags: u64) -> String {
+    let names: Vec<&str> = KPAGEFLAGS
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| flags & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    names.join(" ")
+}
+
+/// Describes where the page at `vaddr` lives according to pagemap, and its
+/// flags according to kpageflags. The PFN is only visible with CAP_SYS_ADMIN.
+fn describe_page(vaddr: usize) -> String {
+    let entry = match read_u64_at("/proc/self/pagemap", (vaddr / PAGE_SIZE) as u64) {
+        Ok(x) => x,
+        Err(err) => return format!("pagemap: unavailable ({})\n", err),
+    };
+    let mut out = format!("pagemap: {:#018x}", entry);
+    let bits = [
+        (PM_PRESENT, "present"),
+        (PM_SWAP, "swapped"),
+        (PM_FILE, "file/shared-anon"),
+        (PM_EXCLUSIVE, "exclusive"),
+        (PM_SOFT_DIRTY, "soft-dirty"),
+    ];
+    bits.iter()
+        .filter(|(bit, _)| entry & bit != 0)
+        .for_each(|(_, name)| out += &format!(" {}", name));
+    out += "\n";
+
+    if entry & PM_SWAP != 0 {
+        let swap_type = entry & 0x1f;
+        let swap_offset = (entry & PM_PFN_MASK) >> 5;
+        out += &format!("swap entry: type {} offset {:#x}\n", swap_type, swap_offset);
+    } else if entry & PM_PRESENT != 0 {
+        let pfn = entry & PM_PFN_MASK;
+        if pfn == 0 {
+            out += "pfn: hidden, run as root to see it\n";
+        } else {
+            out += &format!("pfn: {:#x}\n", pfn);
+            match read_u64_at("/proc/kpageflags", pfn) {
+                Ok(flags) => {
+                    out += &format!("kpageflags: {:#x} {}\n", flags, decode_kpageflags(flags));
+                    let swap_cache = flags & (1 << KPF_SWAPCACHE) != 0;
+                    out += &format!(
+                        "recently swapped in: {}\n",
+                        if swap_cache {
+                            "likely, the page is in the swap cache"
+                        } else {
+                            "no, not in the swap cache"
+                        }
+                    );
+                }
+                Err(err) => out += &format!("kpageflags: unavailable ({})\n", err),
+            }
+        }
+    }
+    out
+}
+
+/// Both pages side by side 16 bytes per line, lines that differ are marked
+/// with a '!'.
+fn hexdiff(expected: &[u8], actual: &[u8]) -> String {
+    let mut out = String::new();
+    out += "      offset  expected                                          actual\n";
+    expected
+        .chunks(16)
+        .zip(actual.chunks(16))
+        .enumerate()
+        .for_each(|(line, (e, a))| {
+            let hex = |bytes: &[u8]| {
+                bytes
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            out += &format!(
+                "{} {:#010x}  {:<48}  {}\n",
+                if e != a { "!" } else { " " },
+                line * 16,
+                hex(e),
+                hex(a)
+            );
+        });
+    out
+}
+
+/// Writes a report of a verification failure in `dir` and returns its path.
+pub fn write_report<P: AsRef<Path>>(
+    dir: P,
+    worker: &str,
+    failure: &VerifyFailure,
+    stride: usize,
+    data: &DataMix,
+    run_parameters: &str,
+) -> Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    let path = dir
+        .as_ref()
+        .join(format!("mstress-forensics-{}-{}.txt", worker, timestamp));
+    let mut file = File::create(&path).context(format!("Could not create {}.", path.display()))?;
+
+    let allocation = &failure.allocation;
+    let slice = allocation.as_slice();
+    writeln!(file, "MSTRESS CORRUPTION REPORT")?;
+    writeln!(file, "worker: {}", worker)?;
+    writeln!(file, "unix time: {}", timestamp)?;
+    writeln!(
+        file,
+        "allocation: {:p} size {:#x} backend {} seed {:#018x}",
+        allocation.ptr,
+        allocation.size,
+        allocation.backend.name(),
+        allocation.seed
+    )?;
+    writeln!(file, "bad pages: {}", failure.bad_offsets.len())?;
+    writeln!(file, "\n{}", failure.message)?;
+    writeln!(file, "RUN PARAMETERS\n{}\n", run_parameters)?;
+
+    for (i, offset) in failure.bad_offsets.iter().enumerate() {
+        let page_index = offset / PAGE_SIZE;
+        let page_start = page_index * PAGE_SIZE;
+        let page_end = (page_start + PAGE_SIZE).min(slice.len());
+        let vaddr = allocation.ptr as usize + page_start;
+        let actual = &slice[page_start..page_end];
+        let expected = allocation.expected_page(page_index, stride, data);
+        // The checksum verifier only knows the page is bad, not where.
+        let first_bad = expected
+            .iter()
+            .zip(actual)
+            .position(|(a, b)| a != b)
+            .unwrap_or(offset % PAGE_SIZE);
+        writeln!(
+            file,
+            "PAGE {} vaddr {:#x} first bad byte {:#x} ({:#x} in the page)",
+            page_index,
+            vaddr,
+            vaddr + first_bad,
+            first_bad
+        )?;
+        writeln!(file, "corruption: {}", failure.corruptions[i])?;
+        write!(file, "{}", describe_page(vaddr))?;
+        if i < MAX_DETAILED_PAGES {
+            write!(file, "{}", hexdiff(&expected, actual))?;
+        }
+        writeln!(file)?;
+    }
+    if failure.bad_offsets.len() > MAX_DETAILED_PAGES {
+        writeln!(
+            file,
+            "Only the first {} pages have a hexdiff.",
+            MAX_DETAILED_PAGES
+        )?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_flags() {
+        assert_eq!(decode_kpageflags(0), "");
+        assert_eq!(
+            decode_kpageflags(1 << 4 | 1 << 5 | 1 << 12 | 1 << 14),
+            "DIRTY LRU ANON SWAPBACKED"
+        );
+        // Bits past the known flags are ignored.
+        assert_eq!(decode_kpageflags(1 << 26 | 1 << 40), "PGTABLE");
+    }
+
+    #[test]
+    fn hexdiff_marks_lines() {
+        let expected = [0u8; 40];
+        let mut actual = expected;
+        actual[17] = 0xff;
+        let diff = hexdiff(&expected, &actual);
+        let lines: Vec<&str> = diff.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains("expected"));
+        assert!(lines[1].starts_with("  0x00000000  00 00"));
+        assert!(lines[2].starts_with("! 0x00000010  00 00"));
+        assert!(lines[2].ends_with("00 ff 00 00 00 00 00 00 00 00 00 00 00 00 00 00"));
+        assert!(lines[3].starts_with("  0x00000020  00 00 00 00 00 00 00 00  "));
+    }
+}
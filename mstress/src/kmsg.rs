@@ -0,0 +1,185 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::ffi::CString;
+
+const KMSG: &str = "/dev/kmsg";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KernelEventKind {
+    Warn,
+    Bug,
+    Oops,
+    Oom,
+}
+
+/// A kernel log line worth looking at.
+#[derive(Clone, Debug, Serialize)]
+pub struct KernelEvent {
+    pub kind: KernelEventKind,
+    pub seq: u64,
+    pub timestamp_us: u64,
+    pub message: String,
+}
+
+/// The "cut here" line opening a splat is skipped, the line after it tells a
+/// WARN from a BUG.
+fn classify(message: &str) -> Option<KernelEventKind> {
+    if message.starts_with("WARNING:") {
+        Some(KernelEventKind::Warn)
+    } else if message.starts_with("BUG:") || message.contains("kernel BUG at") {
+        Some(KernelEventKind::Bug)
+    } else if message.starts_with("Oops") || message.contains("general protection fault") {
+        Some(KernelEventKind::Oops)
+    } else if message.contains("invoked oom-killer")
+        || message.starts_with("Out of memory")
+        || message.starts_with("Memory cgroup out of memory")
+    {
+        Some(KernelEventKind::Oom)
+    } else {
+        None
+    }
+}
+
+/// Parses a /dev/kmsg record, "<prio>,<seq>,<timestamp_us>,<flags>;<message>"
+/// followed by continuation lines starting with a space.
+fn parse_record(record: &str) -> Option<KernelEvent> {
+    let (header, message) = record.split_once(';')?;
+    let message = message.lines().next().unwrap_or("").trim_end().to_owned();
+    let kind = classify(&message)?;
+    let mut fields = header.split(',');
+    let _prio = fields.next();
+    let seq = fields.next()?.parse::<u64>().ok()?;
+    let timestamp_us = fields.next()?.parse::<u64>().ok()?;
+    Some(KernelEvent {
+        kind,
+        seq,
+        timestamp_us,
+        message,
+    })
+}
+
+/// Reads the kernel log records written after it was opened.
+pub struct KmsgReader {
+    fd: libc::c_int,
+    buf: Vec<u8>,
+}
+
+impl KmsgReader {
+    pub fn open() -> Result<KmsgReader> {
+        let path = CString::new(KMSG)?;
+        let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDONLY | libc::O_NONBLOCK) };
+        if fd < 0 {
+            bail!(
+                "Could not open {}: {}",
+                KMSG,
+                std::io::Error::last_os_error()
+            );
+        }
+        // Skip what was logged before the run.
+        unsafe { libc::lseek(fd, 0, libc::SEEK_END) };
+        Ok(KmsgReader {
+            fd,
+            buf: vec![0u8; 8192],
+        })
+    }
+
+    /// Returns the interesting events among the records available now,
+    /// without blocking.
+    pub fn read_events(&mut self) -> Result<Vec<KernelEvent>> {
+        let mut events = Vec::new();
+        loop {
+            // Every read returns exactly one record.
+            let n = unsafe {
+                libc::read(
+                    self.fd,
+                    self.buf.as_mut_ptr() as *mut libc::c_void,
+                    self.buf.len(),
+                )
+            };
+            if n < 0 {
+                let err = std::io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EAGAIN) => break,
+                    // Records were overwritten before we read them, the next
+                    // read continues from the oldest available one.
+                    Some(libc::EPIPE) | Some(libc::EINTR) => continue,
+                    _ => bail!("Could not read {}: {}", KMSG, err),
+                }
+            }
+            let record = String::from_utf8_lossy(&self.buf[..n as usize]);
+            if let Some(event) = parse_record(&record) {
+                events.push(event);
+            }
+        }
+        Ok(events)
+    }
+}
+
+impl Drop for KmsgReader {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_messages() {
+        for (message, kind) in [
+            (
+                "WARNING: CPU: 1 PID: 42 at mm/zswap.c:123 zswap_store+0x10/0x20",
+                Some(KernelEventKind::Warn),
+            ),
+            (
+                "BUG: Bad page state in process mstress  pfn:12345",
+                Some(KernelEventKind::Bug),
+            ),
+            (
+                "kernel BUG at mm/swap_state.c:99!",
+                Some(KernelEventKind::Bug),
+            ),
+            ("Oops: 0000 [#1] PREEMPT SMP", Some(KernelEventKind::Oops)),
+            (
+                "general protection fault, probably for non-canonical address",
+                Some(KernelEventKind::Oops),
+            ),
+            (
+                "mstress invoked oom-killer: gfp_mask=0xcc0(GFP_KERNEL), order=0",
+                Some(KernelEventKind::Oom),
+            ),
+            (
+                "Out of memory: Killed process 42 (mstress)",
+                Some(KernelEventKind::Oom),
+            ),
+            (
+                "Memory cgroup out of memory: Killed process 42 (mstress)",
+                Some(KernelEventKind::Oom),
+            ),
+            ("------------[ cut here ]------------", None),
+            ("Adding 1048572k swap on /swapfile.", None),
+        ] {
+            assert_eq!(classify(message), kind, "{}", message);
+        }
+    }
+
+    #[test]
+    fn parse_records() {
+        let event = parse_record(
+            "4,1234,5678901,-;WARNING: CPU: 1 PID: 42 at mm/zswap.c:123 zswap_store\n SUBSYSTEM=mm\n",
+        )
+        .unwrap();
+        assert_eq!(event.kind, KernelEventKind::Warn);
+        assert_eq!(event.seq, 1234);
+        assert_eq!(event.timestamp_us, 5678901);
+        assert_eq!(
+            event.message,
+            "WARNING: CPU: 1 PID: 42 at mm/zswap.c:123 zswap_store"
+        );
+        assert!(parse_record("6,1,2,-;zswap: loaded using pool lzo/zbud").is_none());
+        assert!(parse_record("4,x,2,-;BUG: oops").is_none());
+        assert!(parse_record("BUG: no header").is_none());
+    }
+}
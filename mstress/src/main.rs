@@ -1,15 +1,59 @@
 use anyhow::{bail, Context, Result};
 use byte_unit::Byte;
-use clap::Parser;
-use libc::{free, malloc};
+use clap::{Parser, Subcommand};
 use std::collections::VecDeque;
-use std::process::Command;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
 use std::thread::{sleep, spawn, JoinHandle};
 use std::time::{Duration, Instant};
-use rand::Rng;
+use serde::Serialize;
+use serde_json::json;
+
+mod access;
+mod alloc;
+mod cgroup;
+mod control;
+mod data;
+mod export;
+mod flip;
+mod forensics;
+mod kmsg;
+mod meminfo;
+mod psi;
+mod reclaim;
+mod scenario;
+mod scrub;
+mod swap;
+mod sweep;
+mod trace;
+mod verify;
+mod vmstat;
+mod zram;
+mod zswap;
+
+use access::{AccessPattern, Accessor};
+use alloc::AllocBackend;
+use control::{parse_command, send_request, ControlCommand, ControlServer};
+use cgroup::{parse_cgroup, parse_cgroup_limit, render_cgroup_stats, Cgroup, CgroupLimits, CgroupStats};
+use data::{parse_data_mix, DataMix, PAGE_SIZE};
+use export::{Exporter, OutputFormat};
+use flip::{parse_flip, Flip, FlipResult, ZswapFlipper};
+use forensics::write_report;
+use kmsg::{KernelEvent, KmsgReader};
+use meminfo::{parse_meminfo, render_free_stats, FreeStats};
+use psi::{parse_psi, parse_psi_trigger, render_psi_stats, PsiStats, PsiTrigger};
+use reclaim::{reclaim, Reclaim, ReclaimMethod};
+use scenario::{load_scenario, Phase};
+use scrub::{ScrubFailure, Scrubber};
+use swap::{create_swapfile, parse_swaps, render_swaps, swap_on, SwapCycle, SwapCycler, SwapDevice};
+use sweep::{parse_sweep_param, render_sweep, run_sweep};
+use trace::Tracer;
+use verify::{make_allocation, verify_and_free, Allocation, MarkerTag, VerifyMode};
+use vmstat::{parse_vmstat, render_vmstat_stats, VmstatStats};
+use zram::{parse_zram, render_zram_stats, Zram, ZramStats};
+use zswap::{parse_zswap, render_zswap_stats, ZswapStats};
 
 fn u8_percent(s: &str) -> Result<u8> {
     let parsed = s.parse::<u8>()?;
@@ -19,8 +63,83 @@ fn u8_percent(s: &str) -> Result<u8> {
     Ok(parsed)
 }
 
-#[derive(Parser, Clone)]
+fn f64_percent(s: &str) -> Result<f64> {
+    let parsed = s.parse::<f64>()?;
+    if !(0.0..=100.0).contains(&parsed) {
+        bail!("Percent value must be between 0 and 100.");
+    }
+    Ok(parsed)
+}
+
+/// A period in seconds, finite and above 0.
+fn positive_seconds(s: &str) -> Result<f64> {
+    let parsed = s.parse::<f64>()?;
+    if !parsed.is_finite() || parsed <= 0.0 {
+        bail!("Seconds must be a number above 0.");
+    }
+    Ok(parsed)
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Sends a request to the control socket of a running mstress: "threads
+    /// <n>", "thread-bytes <size>", "hold-time-ms <ms>", "data-profile
+    /// <mix>", "pause", "resume" or "stats".
+    Ctl {
+        #[clap(long, default_value = "/tmp/mstress.sock")]
+        socket: String,
+
+        request: Vec<String>,
+    },
+    /// Runs the workload given after "--" once per combination of zswap
+    /// parameter values and compares the runs, e.g. "sweep --param
+    /// compressor=lzo,zstd --param max_pool_percent=10,20 -- -j 4 -b 1000000000".
+    Sweep {
+        /// Zswap parameter and the values to try. Can be repeated.
+        #[clap(long, required = true, value_parser=parse_sweep_param)]
+        param: Vec<Flip>,
+
+        /// How long the workload runs for each configuration.
+        #[clap(long, default_value_t = 60)]
+        run_seconds: u64,
+
+        /// Pause between two runs, to let the swap and the zswap pool drain.
+        /// Pages of other processes are not drained.
+        #[clap(long, default_value_t = 5)]
+        settle_seconds: u64,
+
+        /// Where the output and the log of every run and the report go.
+        #[clap(long, default_value = ".")]
+        output_dir: String,
+
+        /// Arguments of the workload, --headless, --timeout-seconds, --output
+        /// and --output-format are set by the sweep.
+        #[clap(last = true)]
+        workload: Vec<String>,
+    },
+    /// Creates a swap file, writing the swap header itself.
+    Mkswap {
+        path: String,
+
+        /// Size of the file, e.g. "1GiB".
+        #[clap(long)]
+        size: String,
+
+        /// Enable the swap file once created.
+        #[clap(long)]
+        swapon: bool,
+
+        #[clap(long)]
+        priority: Option<i32>,
+    },
+}
+
+#[derive(Parser, Clone, Debug)]
+#[clap(args_conflicts_with_subcommands = true)]
 struct CliArgs {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     #[clap(short = 'j', long, default_value_t = 1)]
     threads: u16,
 
@@ -47,48 +166,374 @@ struct CliArgs {
 
     #[clap(long, default_value_t = 0, value_parser=u8_percent)]
     rand_data_percent: u8,
-}
 
-#[derive(Default)]
-struct FreeStats {
-    mem_total: u128,
-    mem_available: u128,
-    swap_total: u128,
-    swap_available: u128,
-}
+    /// Page content profiles mixed by percentage, e.g. "random" or
+    /// "zero:20,same-filled:10,text:40,ratio=3:30". Replaces --rand-data-percent.
+    #[clap(long, value_parser=parse_data_mix, conflicts_with = "rand_data_percent")]
+    data_profile: Option<DataMix>,
 
-#[derive(Default)]
-struct ZswapStats {
-    written_back: u128,
-    rejects: u128,
-    pool_size: u128,
+    /// Stop the run when the memory PSI "some" avg10 reaches this percent.
+    #[clap(long, value_parser=f64_percent)]
+    abort_on_psi_some: Option<f64>,
+
+    /// Stop the run when the memory PSI "full" avg10 reaches this percent.
+    #[clap(long, value_parser=f64_percent)]
+    abort_on_psi_full: Option<f64>,
+
+    /// Pause the workers while the memory PSI "some" avg10 is above this percent.
+    #[clap(long, value_parser=f64_percent)]
+    pause_on_psi_some: Option<f64>,
+
+    /// Pause the workers while the memory PSI "full" avg10 is above this percent.
+    #[clap(long, value_parser=f64_percent)]
+    pause_on_psi_full: Option<f64>,
+
+    /// Resume the paused workers once the memory PSI "some" avg10 is below
+    /// this percent, 80% of --pause-on-psi-some by default.
+    #[clap(long, value_parser=f64_percent, requires = "pause_on_psi_some")]
+    resume_on_psi_some: Option<f64>,
+
+    /// Resume the paused workers once the memory PSI "full" avg10 is below
+    /// this percent, 80% of --pause-on-psi-full by default.
+    #[clap(long, value_parser=f64_percent, requires = "pause_on_psi_full")]
+    resume_on_psi_full: Option<f64>,
+
+    /// Sample stats when this PSI trigger fires instead of only every refresh
+    /// period, e.g. "some 150000 1000000".
+    #[clap(long, value_parser=parse_psi_trigger)]
+    psi_trigger: Option<String>,
+
+    /// Record every stats sample and worker event to this file.
+    #[clap(short, long)]
+    output: Option<String>,
+
+    #[clap(long, value_enum, default_value_t = OutputFormat::Csv)]
+    output_format: OutputFormat,
+
+    /// Don't redraw the screen, print a progress line periodically and a
+    /// summary at the end. Exits with 1 on corruption, 2 on allocation
+    /// failure, 3 on stats failure, 4 on a kernel warning with
+    /// --stop-on-kernel-warning and 5 on setup failure.
+    #[clap(long)]
+    headless: bool,
+
+    #[clap(long, default_value_t = 10)]
+    progress_interval_seconds: u64,
+
+    #[clap(long, value_enum, default_value_t = AllocBackend::Malloc)]
+    alloc_backend: AllocBackend,
+
+    /// Number of workers, out of --threads, allocating hugetlb pages.
+    #[clap(long, default_value_t = 0)]
+    hugetlb_workers: u16,
+
+    /// Number of workers, out of --threads, allocating madvised THPs.
+    #[clap(long, default_value_t = 0)]
+    thp_workers: u16,
+
+    /// Allocation size of the hugetlb workers, defaults to the size of the
+    /// other workers. Keep it within the reserved vm.nr_hugepages.
+    #[clap(long)]
+    hugetlb_bytes: Option<usize>,
+
+    #[clap(long, value_enum, default_value_t = VerifyMode::Markers)]
+    verify_mode: VerifyMode,
+
+    /// Directory where the corruption reports are written.
+    #[clap(long, default_value = ".")]
+    forensics_dir: String,
+
+    /// Tracepoints to enable at startup, a group (zswap, vmscan, swap, kmem)
+    /// or a single event (vmscan:mm_vmscan_direct_reclaim_begin). Can be
+    /// repeated.
+    #[clap(long)]
+    trace_event: Vec<String>,
+
+    /// Size of the per cpu trace ring buffer in KiB.
+    #[clap(long)]
+    trace_buffer_kb: Option<u64>,
+
+    /// Trace event filter as <event>=<filter>, e.g.
+    /// "kmem:mm_page_alloc=order>0". Can be repeated.
+    #[clap(long)]
+    trace_filter: Vec<String>,
+
+    /// Where the trace is written when a thread fails.
+    #[clap(long, default_value = "/shared/logs.txt")]
+    trace_output: String,
+
+    /// Stop the run on the first WARN, BUG, Oops or OOM kill in the kernel
+    /// log.
+    #[clap(long)]
+    stop_on_kernel_warning: bool,
+
+    /// How the workers touch their allocation while holding it.
+    #[clap(long, value_enum, default_value_t = AccessPattern::None)]
+    access_pattern: AccessPattern,
+
+    /// Pages touched per second by each worker while holding.
+    #[clap(long, default_value_t = 1000)]
+    access_rate: u64,
+
+    /// Percent of the pages that are hot with --access-pattern hot-cold.
+    #[clap(long, default_value_t = 10.0, value_parser=f64_percent)]
+    hot_percent: f64,
+
+    /// Exponent of --access-pattern zipfian, the higher the more skewed.
+    #[clap(long, default_value_t = 1.0)]
+    zipf_exponent: f64,
+
+    /// Re-verify the held allocations this often instead of only before
+    /// freeing them.
+    #[clap(long)]
+    scrub_interval_ms: Option<u64>,
+
+    /// Percent of the pages, picked at random, checked by each scrub.
+    #[clap(long, default_value_t = 100.0, value_parser=f64_percent)]
+    scrub_percent: f64,
+
+    /// Percent of the pages, picked at random, read and then rewritten with a
+    /// new generation during the hold phase, to dirty swapped in pages.
+    #[clap(long, default_value_t = 0.0, value_parser=f64_percent)]
+    rewrite_percent: f64,
+
+    /// Number of rewrites per allocation, evenly spread over the hold phase.
+    #[clap(long, default_value_t = 1)]
+    rewrite_passes: u32,
+
+    /// TOML file describing phases run in order, each overriding the
+    /// threads, bytes, hold time and data profile of the command line.
+    #[clap(long)]
+    scenario: Option<String>,
+
+    /// Listen on this Unix socket for requests changing the workers on the
+    /// fly, see the ctl subcommand.
+    #[clap(long)]
+    control_socket: Option<String>,
+
+    /// Zswap module parameter cycled through the given values during the run,
+    /// e.g. "max_pool_percent=10,50" or "compressor=lzo,zstd". Can be repeated.
+    #[clap(long, value_parser=parse_flip)]
+    zswap_flip: Vec<Flip>,
+
+    /// Seconds between two flips of the --zswap-flip parameters.
+    #[clap(long, default_value_t = 1.0, value_parser=positive_seconds)]
+    zswap_flip_seconds: f64,
+
+    /// Flips max_pool_percent between 0 and its current value this often,
+    /// same as --zswap-flip max_pool_percent=0,<current> --zswap-flip-seconds.
+    /// Can't be combined with --zswap-flip.
+    #[clap(long, value_parser=positive_seconds, conflicts_with = "zswap_flip")]
+    max_pool_percent_flip_seconds: Option<f64>,
+
+    /// Swap device or file swapped off and back on in turn during the run, to
+    /// stress the swapoff path while the workers verify. Can be repeated.
+    #[clap(long)]
+    swap_cycle: Vec<String>,
+
+    /// Seconds between two swapoff/swapon cycles.
+    #[clap(long, default_value_t = 10.0, value_parser=positive_seconds)]
+    swap_cycle_seconds: f64,
+
+    /// Swap the cycled device back on above the others, so that the
+    /// preferred device changes every cycle.
+    #[clap(long)]
+    swap_cycle_priorities: bool,
+
+    /// Set up a zram device of this size, e.g. "2GiB", as swap for the run.
+    #[clap(long)]
+    zram_size: Option<String>,
+
+    /// Compression algorithm of the zram device, the kernel default if unset.
+    #[clap(long)]
+    zram_algorithm: Option<String>,
+
+    /// Block device the zram device can write pages back to. mstress doesn't
+    /// trigger the writeback, write e.g. "huge" or "idle" to
+    /// /sys/block/zramN/writeback during the run for that.
+    #[clap(long)]
+    zram_backing_dev: Option<String>,
+
+    #[clap(long, default_value_t = 100)]
+    zram_priority: i32,
+
+    /// Push the pages of every allocation out of memory before verifying it,
+    /// so that each verification reads them back from swap regardless of the
+    /// memory pressure. The hugetlb workers are skipped.
+    #[clap(long, value_enum, default_value_t = ReclaimMethod::None)]
+    reclaim: ReclaimMethod,
+
+    /// Run in a child cgroup of the current one, removed at the end. Implied
+    /// by the --cgroup-* limits.
+    #[clap(long)]
+    cgroup: bool,
+
+    /// memory.max of the cgroup, a size or "max".
+    #[clap(long, value_parser=parse_cgroup_limit)]
+    cgroup_memory_max: Option<String>,
+
+    /// memory.high of the cgroup, a size or "max".
+    #[clap(long, value_parser=parse_cgroup_limit)]
+    cgroup_memory_high: Option<String>,
+
+    /// memory.low of the cgroup, a size or "max".
+    #[clap(long, value_parser=parse_cgroup_limit)]
+    cgroup_memory_low: Option<String>,
+
+    /// memory.swap.max of the cgroup, a size or "max".
+    #[clap(long, value_parser=parse_cgroup_limit)]
+    cgroup_swap_max: Option<String>,
+
+    /// memory.zswap.max of the cgroup, a size or "max".
+    #[clap(long, value_parser=parse_cgroup_limit)]
+    cgroup_zswap_max: Option<String>,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize)]
 struct MemStats {
     free: FreeStats,
     zswap: ZswapStats,
+    vmstat: VmstatStats,
+    psi: PsiStats,
+    swaps: Vec<SwapDevice>,
+    zram: Vec<ZramStats>,
+    cgroup: Option<CgroupStats>,
 }
 
+const STATS_HISTORY_LEN: usize = 600;
+
 struct State {
     target: Byte,
     start_time: Instant,
     mem_stats: MemStats,
     workers: Vec<WorkerState>,
     verifications: u128,
+    paused: bool,
+    // Paused from the control socket, independently of PSI.
+    control_paused: bool,
+    exporter: Option<Exporter>,
+    peak_swap_used: u128,
+    peak_zswap_pool: u128,
+    peak_zram_used: u128,
+    errors: Vec<String>,
+    failure: Option<FailureKind>,
+    kernel_events: Vec<KernelEvent>,
+    // Name of the current phase, only when running a scenario.
+    phase: Option<String>,
+    // When the zswap parameters were last flipped and to what.
+    last_flip: Option<(Instant, String)>,
+    // When a swap device was last cycled and how it went.
+    last_swap_cycle: Option<(Instant, String)>,
+    // Where the pages of the last reclaimed allocation ended up.
+    last_reclaim: Option<String>,
+    // Recent samples, to tell what was going on when a scrub failed.
+    stats_history: VecDeque<(Instant, MemStats)>,
+}
+
+impl State {
+    fn record<T: Serialize>(&mut self, kind: &str, data: &T) {
+        if let Some(exporter) = self.exporter.as_mut() {
+            if let Err(err) = exporter.record(kind, data) {
+                println!("Could not write to output file, disabling it.\n{}", err);
+                self.exporter = None;
+            }
+        }
+    }
+
+    fn push_history(&mut self) {
+        if self.stats_history.len() >= STATS_HISTORY_LEN {
+            self.stats_history.pop_front();
+        }
+        self.stats_history
+            .push_back((Instant::now(), self.mem_stats.clone()));
+    }
+
+    /// The sample received closest to `at`.
+    fn closest_sample(&self, at: Instant) -> Option<&(Instant, MemStats)> {
+        self.stats_history.iter().min_by_key(|(time, _)| {
+            if *time > at {
+                *time - at
+            } else {
+                at - *time
+            }
+        })
+    }
+
+    fn reset_workers(&mut self, threads: u16, thread_allocation_size: usize) {
+        self.target = Byte::from_bytes((thread_allocation_size as u128) * threads as u128);
+        self.workers = (0..threads).map(|_| WorkerState::Allocating).collect();
+    }
+
+    fn update_peaks(&mut self) {
+        let free = &self.mem_stats.free;
+        let swap_used = free.swap_total.saturating_sub(free.swap_available);
+        self.peak_swap_used = self.peak_swap_used.max(swap_used);
+        let zswap_pool = self
+            .mem_stats
+            .zswap
+            .get("pool_total_size")
+            .or(free.zswap)
+            .unwrap_or(0);
+        self.peak_zswap_pool = self.peak_zswap_pool.max(zswap_pool);
+        let zram_used = self
+            .mem_stats
+            .zram
+            .iter()
+            .filter_map(|x| x.get("mem_used_total"))
+            .sum();
+        self.peak_zram_used = self.peak_zram_used.max(zram_used);
+    }
+
+    fn flush_output(&mut self) {
+        if let Some(exporter) = self.exporter.as_mut() {
+            if let Err(err) = exporter.flush() {
+                println!("Could not flush output file.\n{}", err);
+            }
+        }
+    }
 }
 
+#[derive(Clone, Copy, Serialize)]
 enum WorkerState {
     Allocating,
     Holding,
+    Reclaiming,
     Verifying,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+enum FailureKind {
+    Verification,
+    Allocation,
+    Stats,
+    Kernel,
+    Setup,
+}
+
+impl FailureKind {
+    fn exit_code(&self) -> i32 {
+        match self {
+            FailureKind::Verification => 1,
+            FailureKind::Allocation => 2,
+            FailureKind::Stats => 3,
+            FailureKind::Kernel => 4,
+            FailureKind::Setup => 5,
+        }
+    }
+}
+
 enum Message {
     WorkerState(u16, WorkerState),
-    MemStats(MemStats),
-    ThreadError(String, String),
+    MemStats(Box<MemStats>),
+    ThreadError(String, FailureKind, String),
     VerificationCompleted,
+    KernelEvent(KernelEvent),
+    ScrubFailure(String, ScrubFailure),
+    // The request line, the parsed command and where to send the response.
+    Control(String, ControlCommand, Sender<String>),
+    ZswapFlip(FlipResult),
+    SwapCycle(SwapCycle),
+    Reclaim(String, Reclaim),
 }
 
 struct ThreadPayload {
@@ -96,8 +541,12 @@ struct ThreadPayload {
     args: CliArgs,
     thread_allocation_size: usize,
     running: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
     tx: Sender<Message>,
-    rand_data_len: usize,
+    data: DataMix,
+    tracer: Option<Tracer>,
+    // The cgroup the process was moved into, if any.
+    cgroup: Option<PathBuf>,
 }
 
 impl ThreadPayload {
@@ -107,8 +556,11 @@ impl ThreadPayload {
             args: self.args.clone(),
             thread_allocation_size: self.thread_allocation_size,
             running: self.running.clone(),
+            paused: self.paused.clone(),
             tx: self.tx.clone(),
-            rand_data_len: self.rand_data_len.clone(),
+            data: self.data.clone(),
+            tracer: self.tracer.clone(),
+            cgroup: self.cgroup.clone(),
         }
     }
 
@@ -118,8 +570,8 @@ impl ThreadPayload {
             .expect("Could not send message from thread.");
     }
 
-    fn error(&self, msg: String) {
-        self.send(Message::ThreadError(self.id.clone(), msg));
+    fn error(&self, kind: FailureKind, msg: String) {
+        self.send(Message::ThreadError(self.id.clone(), kind, msg));
     }
 }
 
@@ -129,65 +581,6 @@ fn fmtb(bytes: u128) -> String {
         .to_string()
 }
 
-fn exec<T: AsRef<std::ffi::OsStr>>(parts: &[T]) -> Result<String> {
-    let mut cmd = Command::new(&parts[0]);
-    for i in 1..parts.len() {
-        cmd.arg(&parts[i]);
-    }
-
-    let output = cmd.output()?;
-    if !output.status.success() {
-        bail!("Command failed.");
-    }
-    let s = String::from_utf8_lossy(&output.stdout).into_owned();
-    Ok(s)
-}
-
-fn parse_free() -> Result<FreeStats> {
-    let txt = exec(&["free", "-b"])?;
-    let lines: Vec<Vec<String>> = txt
-        .lines()
-        .map(|x| x.split_whitespace().map(|y| y.to_owned()).collect())
-        .collect();
-    let mem_line = lines
-        .get(1)
-        .context("Could not find mem line in free output.")?;
-    let mem_total = mem_line
-        .get(1)
-        .map(|x| x.parse::<u128>())
-        .context("Could not find mem total in free output")??;
-    let mem_available = mem_line
-        .last()
-        .map(|x| x.parse::<u128>())
-        .context("Could not find mem total in free output")??;
-    let mut stats = FreeStats {
-        mem_total,
-        mem_available,
-        ..Default::default()
-    };
-    if let Some(line) = lines.get(2) {
-        if let Some(x) = line.get(0) {
-            if x == "Swap:" {
-                if let Some(Ok(n)) = line.get(1).map(|x| x.parse::<u128>()) {
-                    stats.swap_total = n;
-                }
-                if let Some(Ok(n)) = line.get(3).map(|x| x.parse::<u128>()) {
-                    stats.swap_available = n;
-                }
-            }
-        }
-    }
-    Ok(stats)
-}
-
-fn parse_zswap() -> Result<ZswapStats> {
-    Ok(ZswapStats {
-        written_back: read_swap_param("written_back_pages")?,
-        rejects: read_swap_param("reject_reclaim_fail")?,
-        pool_size: read_swap_param("stored_pages")?,
-    })
-}
-
 fn setup_ctrl(running: Arc<AtomicBool>) {
     ctrlc::set_handler(move || {
         println!("Ctrl-C received.");
@@ -196,73 +589,85 @@ fn setup_ctrl(running: Arc<AtomicBool>) {
     .expect("Could not set Ctrl-C handler.");
 }
 
-fn make_allocation(size: usize, stride: usize, random_data_len: usize) -> *mut libc::c_void {
-    unsafe {
-        let ptr = malloc(size);
-        let slice: &mut [u8] = std::slice::from_raw_parts_mut(ptr as *mut u8, size);
-        let mut rng = rand::thread_rng();
-        let mut i = 0;
-        let mut index = 0;
-        if random_data_len > 0 {
-            while i < size {
-                rng.fill(&mut slice[i..i+random_data_len]);
-                i += 4096;
-            }
-        }
+/// The first --hugetlb-workers use hugetlb pages, the following --thp-workers
+/// use THPs and the rest use --alloc-backend.
+fn worker_allocation(id: u16, payload: &ThreadPayload) -> (AllocBackend, usize) {
+    let args = &payload.args;
+    if id < args.hugetlb_workers {
+        let size = args
+            .hugetlb_bytes
+            .unwrap_or(payload.thread_allocation_size);
+        (AllocBackend::Hugetlb, size)
+    } else if id < args.hugetlb_workers.saturating_add(args.thp_workers) {
+        (AllocBackend::MmapHugepage, payload.thread_allocation_size)
+    } else {
+        (args.alloc_backend, payload.thread_allocation_size)
+    }
+}
 
-        i = 0;
-        while i < size-8 {
-            slice[i] = 0x11;
-            slice[i + 1] = 0x22;
-            slice[i + 2] = 0x33;
-            slice[i + 3] = 0x44;
-            slice[i + 4] = ((index >> 24) & 0xff) as u8;
-            slice[i + 5] = ((index >> 16) & 0xff) as u8;
-            slice[i + 6] = ((index >> 8) & 0xff) as u8;
-            slice[i + 7] = (index & 0xff) as u8;
-            i += stride;
-            index += 1;
+/// Holds the allocation for `duration`, scrubbing it every scrub interval if
+/// scrubbing is enabled. Stops at the first scrub failure.
+fn hold_scrubbing(
+    accessor: &mut Accessor,
+    scrubber: Option<&mut Scrubber>,
+    allocation: &Allocation,
+    duration: Duration,
+    payload: &ThreadPayload,
+) -> Result<(), ScrubFailure> {
+    let Some(scrubber) = scrubber else {
+        accessor.hold(allocation.as_slice(), duration);
+        return Ok(());
+    };
+    let deadline = Instant::now() + duration;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        accessor.hold(allocation.as_slice(), remaining.min(scrubber.interval));
+        scrubber.scrub(allocation, payload.args.stride, &payload.data)?;
+        if remaining <= scrubber.interval {
+            return Ok(());
         }
-        ptr
     }
 }
 
-fn verify_and_free(size: usize, stride: usize, ptr: *mut libc::c_void) -> Result<()> {
-    unsafe {
-        let slice = std::slice::from_raw_parts_mut(ptr as *mut u8, size);
-        let mut i = 0;
-        let mut index = 0;
-        let mut ring: VecDeque<[u8; 8]> = VecDeque::new();
-        for _ in 0..4 {
-            ring.push_back([0u8; 8]);
-        }
-        while i < size-8 {
-            let failed = slice[i] != 0x11
-                || slice[i + 1] != 0x22
-                || slice[i + 2] != 0x33
-                || slice[i + 3] != 0x44
-                || slice[i + 4] != ((index >> 24) & 0xff) as u8
-                || slice[i + 5] != ((index >> 16) & 0xff) as u8
-                || slice[i + 6] != ((index >> 8) & 0xff) as u8
-                || slice[i + 7] != (index & 0xff) as u8;
-            let mut popped = ring.pop_front().unwrap();
-            popped.clone_from_slice(&slice[i..i + 8]);
-            ring.push_back(popped);
-            if failed {
-                let mut msg = String::new();
-                msg += &format!("Possible memory corruption at {:p} ({:#x}).", &slice[i], i);
-                while !ring.is_empty() {
-                    let popped = ring.pop_front().unwrap();
-                    msg += &format!("\n{:x?}", popped);
-                }
-                msg += " <--- THE BAD GUY\n";
-                bail!(msg);
-            }
-            i += stride;
-            index += 1;
-        }
-        free(ptr)
+/// Holds the allocation for `duration`, rewriting part of it --rewrite-passes
+/// times at even intervals if --rewrite-percent is set. Every rewrite takes a
+/// new `generation`.
+fn hold(
+    accessor: &mut Accessor,
+    mut scrubber: Option<&mut Scrubber>,
+    allocation: &mut Allocation,
+    duration: Duration,
+    generation: &mut u64,
+    payload: &ThreadPayload,
+) -> Result<(), ScrubFailure> {
+    let args = &payload.args;
+    if let Some(scrubber) = scrubber.as_deref_mut() {
+        scrubber.reset(allocation);
+    }
+    let passes = if args.rewrite_percent > 0.0 {
+        args.rewrite_passes
+    } else {
+        0
     };
+    let segment = duration / (passes + 1);
+    for pass in 0..=passes {
+        if pass > 0 {
+            allocation.rewrite(args.rewrite_percent, *generation, args.stride, &payload.data);
+            *generation += 1;
+        }
+        let segment = if pass == passes {
+            duration.saturating_sub(segment * passes)
+        } else {
+            segment
+        };
+        hold_scrubbing(
+            accessor,
+            scrubber.as_deref_mut(),
+            allocation,
+            segment,
+            payload,
+        )?;
+    }
     Ok(())
 }
 
@@ -280,23 +685,113 @@ fn spawn_memory_worker(id: u16, payload: ThreadPayload) -> JoinHandle<String> {
         },
     };
     let sleep_duration = Duration::from_millis(sleep_time_ms);
+    let (backend, allocation_size) = worker_allocation(id, &payload);
 
     spawn(move || {
+        let mut accessor = Accessor::new(
+            payload.args.access_pattern,
+            payload.args.access_rate,
+            payload.args.hot_percent,
+            payload.args.zipf_exponent,
+        );
+        let mut scrubber = payload
+            .args
+            .scrub_interval_ms
+            .map(|ms| Scrubber::new(Duration::from_millis(ms), payload.args.scrub_percent));
+        let mut generation: u64 = 0;
         while payload.running.load(Ordering::SeqCst) {
+            if payload.paused.load(Ordering::SeqCst) {
+                sleep(Duration::from_millis(100));
+                continue;
+            }
             payload.send(Message::WorkerState(id, WorkerState::Allocating));
-            let ptr = make_allocation(payload.thread_allocation_size, payload.args.stride, payload.rand_data_len);
-            if ptr.is_null() {
-                payload.error("Allocation failed".to_owned());
+            let allocation = make_allocation(
+                allocation_size,
+                payload.args.stride,
+                &payload.data,
+                backend,
+                payload.args.verify_mode,
+                MarkerTag { worker: id, generation },
+            );
+            generation += 1;
+            let Some(mut allocation) = allocation else {
+                payload.error(
+                    FailureKind::Allocation,
+                    format!("Allocation failed ({} backend).", backend.name()),
+                );
                 break;
-            }
+            };
             payload.send(Message::WorkerState(id, WorkerState::Holding));
-            sleep(sleep_duration);
+            let scrubbed = hold(
+                &mut accessor,
+                scrubber.as_mut(),
+                &mut allocation,
+                sleep_duration,
+                &mut generation,
+                &payload,
+            );
+            let scrub_msg = match &scrubbed {
+                Ok(()) => String::new(),
+                Err(scrub_failure) => {
+                    if let Some(tracer) = &payload.tracer {
+                        let _ = tracer.stop();
+                    }
+                    payload.send(Message::ScrubFailure(
+                        payload.id.clone(),
+                        scrub_failure.clone(),
+                    ));
+                    format!(
+                        "Scrub found {} bad pages {:?}.\n",
+                        scrub_failure.pages.len(),
+                        scrub_failure.pages
+                    )
+                }
+            };
+
+            // Hugetlb pages can't be swapped, madvise refuses them.
+            if payload.args.reclaim != ReclaimMethod::None && backend != AllocBackend::Hugetlb {
+                payload.send(Message::WorkerState(id, WorkerState::Reclaiming));
+                payload.send(Message::Reclaim(
+                    payload.id.clone(),
+                    reclaim(&allocation, payload.args.reclaim),
+                ));
+            }
 
             payload.send(Message::WorkerState(id, WorkerState::Verifying));
-            if let Err(err) =
-                verify_and_free(payload.thread_allocation_size, payload.args.stride, ptr)
-            {
-                payload.error(format!("Verification error.\n{}", err));
+            let verified = verify_and_free(allocation, payload.args.stride, &payload.data);
+            if let Err(failure) = verified {
+                // Freeze the ring buffer before doing anything else.
+                if let Some(tracer) = &payload.tracer {
+                    let _ = tracer.stop();
+                }
+                let mut msg = format!(
+                    "Verification error ({} backend).\n{}{}",
+                    backend.name(),
+                    scrub_msg,
+                    failure.message
+                );
+                match write_report(
+                    &payload.args.forensics_dir,
+                    &payload.id,
+                    &failure,
+                    payload.args.stride,
+                    &payload.data,
+                    &format!("{:#?}", payload.args),
+                ) {
+                    Ok(path) => msg += &format!("Forensics report written to {}.\n", path.display()),
+                    Err(err) => msg += &format!("Could not write forensics report.\n{}\n", err),
+                }
+                payload.error(FailureKind::Verification, msg);
+                break;
+            } else if scrubbed.is_err() {
+                payload.error(
+                    FailureKind::Verification,
+                    format!(
+                        "Verification error ({} backend).\n{}The pages read correctly when freeing the allocation.\n",
+                        backend.name(),
+                        scrub_msg
+                    ),
+                );
                 break;
             } else {
                 payload.send(Message::VerificationCompleted);
@@ -306,9 +801,129 @@ fn spawn_memory_worker(id: u16, payload: ThreadPayload) -> JoinHandle<String> {
     })
 }
 
-fn read_swap_param(name: &str) -> Result<u128> {
-    let txt = std::fs::read_to_string(format!("/sys/kernel/debug/zswap/{}", name))?;
-    Ok(txt.trim().parse::<u128>()?)
+fn spawn_kmsg_watcher(payload: ThreadPayload) -> JoinHandle<String> {
+    spawn(move || {
+        let mut reader = match KmsgReader::open() {
+            Ok(x) => x,
+            Err(err) => {
+                // Only fatal if the user asked to stop on kernel warnings.
+                if payload.args.stop_on_kernel_warning {
+                    payload.error(FailureKind::Stats, format!("Error while opening the kernel log.\n{}", err));
+                }
+                return payload.id;
+            }
+        };
+        while payload.running.load(Ordering::SeqCst) {
+            match reader.read_events() {
+                Ok(events) => events
+                    .into_iter()
+                    .for_each(|event| payload.send(Message::KernelEvent(event))),
+                Err(err) => {
+                    payload.error(FailureKind::Stats, format!("Error while reading the kernel log.\n{}", err));
+                    break;
+                }
+            }
+            sleep(Duration::from_millis(200));
+        }
+        payload.id
+    })
+}
+
+fn spawn_control_server(server: ControlServer, payload: ThreadPayload) -> JoinHandle<String> {
+    spawn(move || {
+        while payload.running.load(Ordering::SeqCst) {
+            let request = match server.accept() {
+                Ok(Some(x)) => x,
+                Ok(None) => {
+                    sleep(Duration::from_millis(100));
+                    continue;
+                }
+                Err(err) => {
+                    println!("Could not read control request.\n{}", err);
+                    continue;
+                }
+            };
+            let command = match parse_command(&request.line) {
+                Ok(x) => x,
+                Err(err) => {
+                    request.reply(&format!("error: {}", err));
+                    continue;
+                }
+            };
+            let (reply_tx, reply_rx) = channel();
+            payload.send(Message::Control(request.line.clone(), command, reply_tx));
+            // Restarting the workers waits for them to finish holding.
+            match reply_rx.recv_timeout(Duration::from_secs(120)) {
+                Ok(response) => request.reply(&response),
+                Err(_) => request.reply("error: no response, the run might be over."),
+            }
+        }
+        payload.id
+    })
+}
+
+fn spawn_zswap_flipper(
+    mut flipper: ZswapFlipper,
+    period: Duration,
+    payload: ThreadPayload,
+) -> JoinHandle<String> {
+    spawn(move || {
+        let mut last_flip = Instant::now();
+        while payload.running.load(Ordering::SeqCst) {
+            sleep(Duration::from_millis(50).min(period));
+            if last_flip.elapsed() < period {
+                continue;
+            }
+            last_flip = Instant::now();
+            flipper
+                .flip()
+                .into_iter()
+                .for_each(|result| payload.send(Message::ZswapFlip(result)));
+        }
+        if let Err(err) = flipper.restore() {
+            println!("Could not restore the zswap parameters.\n{}", err);
+        }
+        payload.id
+    })
+}
+
+fn spawn_swap_cycler(
+    mut cycler: SwapCycler,
+    period: Duration,
+    payload: ThreadPayload,
+) -> JoinHandle<String> {
+    spawn(move || {
+        let mut last_cycle = Instant::now();
+        while payload.running.load(Ordering::SeqCst) {
+            sleep(Duration::from_millis(50).min(period));
+            if last_cycle.elapsed() < period {
+                continue;
+            }
+            payload.send(Message::SwapCycle(cycler.cycle()));
+            // swapoff can take a while, count the period from its end.
+            last_cycle = Instant::now();
+        }
+        if let Err(err) = cycler.restore() {
+            println!("Could not restore the swap priorities.\n{}", err);
+        }
+        payload.id
+    })
+}
+
+/// Reads all the stats, the deltas are relative to `prev`.
+fn sample_mem_stats(prev: Option<&MemStats>, cgroup: Option<&Path>) -> Result<MemStats> {
+    Ok(MemStats {
+        free: parse_meminfo().context("Error while reading meminfo.")?,
+        zswap: parse_zswap(prev.map(|x| &x.zswap)),
+        vmstat: parse_vmstat(prev.map(|x| &x.vmstat)).context("Error while reading vmstat.")?,
+        psi: parse_psi().context("Error while reading psi.")?,
+        swaps: parse_swaps().context("Error while reading swaps.")?,
+        zram: parse_zram(),
+        cgroup: cgroup
+            .map(parse_cgroup)
+            .transpose()
+            .context("Error while reading cgroup.")?,
+    })
 }
 
 fn spawn_stats_parser(payload: ThreadPayload) -> JoinHandle<String> {
@@ -320,28 +935,34 @@ fn spawn_stats_parser(payload: ThreadPayload) -> JoinHandle<String> {
                 .send(msg)
                 .expect("Could not send screen renderer message.");
         };
+        let mut prev: Option<MemStats> = None;
+        let trigger = match payload.args.psi_trigger.as_deref().map(PsiTrigger::new) {
+            Some(Ok(x)) => Some(x),
+            Some(Err(err)) => {
+                payload.error(FailureKind::Stats, format!("Error while setting up psi trigger.\n{}", err));
+                return payload.id;
+            }
+            None => None,
+        };
         while payload.running.load(Ordering::SeqCst) {
-            let free_stats = match parse_free() {
+            let stats = match sample_mem_stats(prev.as_ref(), payload.cgroup.as_deref()) {
                 Ok(x) => x,
                 Err(err) => {
-                    payload.error(format!("Error while reading free.\n{}", err));
+                    payload.error(FailureKind::Stats, format!("{:#}", err));
                     break;
                 }
             };
-            let zswap_stats = match parse_zswap() {
-                Ok(x) => x,
-                Err(err) => {
-                    payload.error(format!("Error while reading zswap stats.\n{}", err));
-                    break;
+            prev = Some(stats.clone());
+            send(&payload, Message::MemStats(Box::new(stats)));
+            match &trigger {
+                Some(trigger) => {
+                    if let Err(err) = trigger.wait(sleep_duration) {
+                        payload.error(FailureKind::Stats, format!("Error while waiting for psi trigger.\n{}", err));
+                        break;
+                    }
                 }
-            };
-            let stats = MemStats {
-                free: free_stats,
-                zswap: zswap_stats,
-                ..Default::default()
-            };
-            send(&payload, Message::MemStats(stats));
-            sleep(sleep_duration);
+                None => sleep(sleep_duration),
+            }
         }
         payload.id
     })
@@ -355,8 +976,8 @@ fn fmt_duration(seconds: u64) -> String {
 }
 
 fn get_available_memory() -> Result<u128> {
-    let free_stats =
-        parse_free().context("Could not determine target allocation, failed to parse free.")?;
+    let free_stats = parse_meminfo()
+        .context("Could not determine target allocation, failed to parse meminfo.")?;
     let swap_percent = (free_stats.swap_available as f64 * 0.4) as u128;
     Ok(free_stats.mem_available + swap_percent)
 }
@@ -386,6 +1007,9 @@ fn print_row(items: &[&str], alignments: &str) {
             (3, '<') => print!("{: <20}", item),
             (3, '>') => print!("{: >20}", item),
             (3, '^') => print!("{: ^20}", item),
+            (4, '<') => print!("{: <15}", item),
+            (4, '>') => print!("{: >15}", item),
+            (4, '^') => print!("{: ^15}", item),
             _ => panic!(
                 "Invalid row values len {} align_char {}.",
                 n_cells, align_char
@@ -395,53 +1019,33 @@ fn print_row(items: &[&str], alignments: &str) {
     print!("\n");
 }
 
-fn render_free_stats(stats: &FreeStats) {
-    print_row(&["MEMORY", "available", "total"], "<>>");
-    print_row(
-        &["Mem", &fmtb(stats.mem_available), &fmtb(stats.mem_total)],
-        "<>>",
-    );
-    print_row(
-        &["Swap", &fmtb(stats.swap_available), &fmtb(stats.swap_total)],
-        "<>>",
-    );
-}
-
-fn render_zswap_stats(stats: &ZswapStats) {
-    print_row(&["ZSWAP", "bytes", "pages"], "<>>");
-    print_row(
-        &[
-            "size",
-            &fmtb(stats.pool_size*4096),
-            &stats.pool_size.to_string(),
-        ],
-        "<>>",
-    );
-    print_row(
-        &[
-            "writebacks",
-            &fmtb(stats.written_back * 4096),
-            &stats.written_back.to_string(),
-        ],
-        "<>>",
-    );
-    print_row(&["failures", "-", &stats.rejects.to_string()], "<>>");
-}
-
-const ALLOCATING_VEC: [&str; 3] = ["X", "", ""];
-const HOLDING_VEC: [&str; 3] = ["", "X", ""];
-const VERIFYING_VEC: [&str; 3] = ["", "", "X"];
+const ALLOCATING_VEC: [&str; 4] = ["X", "", "", ""];
+const HOLDING_VEC: [&str; 4] = ["", "X", "", ""];
+const RECLAIMING_VEC: [&str; 4] = ["", "", "X", ""];
+const VERIFYING_VEC: [&str; 4] = ["", "", "", "X"];
 
 fn render_workers_states(states: &[WorkerState]) {
-    print_row(&["Allocating", "Holding", "Verifying"], "^^^");
+    print_row(&["Allocating", "Holding", "Reclaiming", "Verifying"], "^^^^");
     states.iter().for_each(|state| {
         let v = match state {
             WorkerState::Allocating => ALLOCATING_VEC,
             WorkerState::Holding => HOLDING_VEC,
+            WorkerState::Reclaiming => RECLAIMING_VEC,
             WorkerState::Verifying => VERIFYING_VEC,
         };
-        print_row(&v, "^^^");
+        print_row(&v, "^^^^");
+    });
+}
+
+fn render_kernel_events(events: &[KernelEvent]) {
+    if events.is_empty() {
+        return;
+    }
+    print_row(&["KERNEL LOG", &events.len().to_string()], "<>");
+    events.iter().rev().take(5).rev().for_each(|event| {
+        println!("[{:?}] {}", event.kind, event.message);
     });
+    println!();
 }
 
 fn render_state(state: &State) {
@@ -456,96 +1060,813 @@ fn render_state(state: &State) {
 
     print_row(&["Verifications:", &state.verifications.to_string()], "<>");
 
+    if let Some(phase) = &state.phase {
+        print_row(&["Phase:", phase], "<>");
+    }
+
     println!("");
     render_free_stats(&state.mem_stats.free);
     println!("");
-    render_zswap_stats(&state.mem_stats.zswap);
+    render_swaps(&state.mem_stats.swaps);
+    println!();
+    // zram takes the place of zswap as the compressed layer when in use.
+    if state.mem_stats.zram.is_empty() {
+        render_zswap_stats(&state.mem_stats.zswap);
+    } else {
+        render_zram_stats(&state.mem_stats.zram);
+    }
     println!("");
+    render_vmstat_stats(&state.mem_stats.vmstat);
+    println!();
+    render_psi_stats(&state.mem_stats.psi);
+    println!();
+    if let Some(cgroup) = &state.mem_stats.cgroup {
+        render_cgroup_stats(cgroup);
+        println!();
+    }
+    if state.paused {
+        print_row(&["Workers paused, memory pressure above threshold."], "<");
+    }
+    if state.control_paused {
+        print_row(&["Workers paused from the control socket."], "<");
+    }
+    if let Some((time, flip)) = &state.last_flip {
+        print_row(&["Last zswap flip:", flip, &format!("{}s ago", time.elapsed().as_secs())], "<<>");
+    }
+    if let Some((time, cycle)) = &state.last_swap_cycle {
+        print_row(&["Last swap cycle:", cycle, &format!("{}s ago", time.elapsed().as_secs())], "<<>");
+    }
+    if let Some(reclaim) = &state.last_reclaim {
+        print_row(&["Last reclaim:", reclaim], "<<");
+    }
+    render_kernel_events(&state.kernel_events);
     render_workers_states(&state.workers);
 }
 
+fn render(args: &CliArgs, state: &State) {
+    if !args.headless {
+        render_state(state);
+    }
+}
+
+fn fmt_sample(stats: &MemStats) -> String {
+    let free = &stats.free;
+    let psi = &stats.psi;
+    let compressed = if stats.zram.is_empty() {
+        format!(
+            "zswap pool {}",
+            fmtb(stats.zswap.get("pool_total_size").or(free.zswap).unwrap_or(0))
+        )
+    } else {
+        let used = stats.zram.iter().filter_map(|x| x.get("mem_used_total")).sum();
+        format!("zram used {}", fmtb(used))
+    };
+    format!(
+        "mem available {} | swap used {} | {} | psi some {:.2}% full {:.2}%",
+        fmtb(free.mem_available),
+        fmtb(free.swap_total.saturating_sub(free.swap_available)),
+        compressed,
+        psi.some.avg10,
+        psi.full.avg10,
+    )
+}
+
+fn render_progress(state: &State) {
+    println!(
+        "[{}] verifications {} | {}",
+        fmt_duration(state.start_time.elapsed().as_secs()),
+        state.verifications,
+        fmt_sample(&state.mem_stats),
+    );
+}
+
+fn render_summary(state: &State) {
+    println!();
+    print_row(&["SUMMARY"], "<");
+    let duration_str = fmt_duration(state.start_time.elapsed().as_secs());
+    print_row(&["Duration:", &duration_str], "<>");
+    print_row(&["Verifications:", &state.verifications.to_string()], "<>");
+    if let Some(phase) = &state.phase {
+        print_row(&["Last phase:", phase], "<>");
+    }
+    print_row(&["Peak swap used:", &fmtb(state.peak_swap_used)], "<>");
+    print_row(&["Peak zswap pool:", &fmtb(state.peak_zswap_pool)], "<>");
+    if state.peak_zram_used > 0 {
+        print_row(&["Peak zram used:", &fmtb(state.peak_zram_used)], "<>");
+    }
+    print_row(&["Kernel events:", &state.kernel_events.len().to_string()], "<>");
+    state
+        .kernel_events
+        .iter()
+        .for_each(|event| println!("[{:?}] {}", event.kind, event.message));
+    print_row(&["Errors:", &state.errors.len().to_string()], "<>");
+    state.errors.iter().for_each(|err| println!("{}", err));
+}
+
+/// Returns the reason to abort the run if any of the PSI abort thresholds was
+/// reached.
+fn psi_abort_reason(args: &CliArgs, psi: &PsiStats) -> Option<String> {
+    if !psi.available {
+        return None;
+    }
+    if let Some(threshold) = args.abort_on_psi_some {
+        if psi.some.avg10 >= threshold {
+            return Some(format!(
+                "PSI some avg10 {:.2}% reached the abort threshold {:.2}%.",
+                psi.some.avg10, threshold
+            ));
+        }
+    }
+    if let Some(threshold) = args.abort_on_psi_full {
+        if psi.full.avg10 >= threshold {
+            return Some(format!(
+                "PSI full avg10 {:.2}% reached the abort threshold {:.2}%.",
+                psi.full.avg10, threshold
+            ));
+        }
+    }
+    None
+}
+
+/// Once paused, the workers only resume when the pressure drops below the
+/// resume thresholds, so that they don't flap around the pause ones.
+fn psi_should_pause(args: &CliArgs, psi: &PsiStats, paused: bool) -> bool {
+    if !psi.available {
+        return false;
+    }
+    let above = |avg10: f64, pause: Option<f64>, resume: Option<f64>| match pause {
+        Some(pause) if paused => avg10 >= resume.unwrap_or(pause * 0.8),
+        Some(pause) => avg10 >= pause,
+        None => false,
+    };
+    above(psi.some.avg10, args.pause_on_psi_some, args.resume_on_psi_some)
+        || above(psi.full.avg10, args.pause_on_psi_full, args.resume_on_psi_full)
+}
+
+fn cgroup_limits(args: &CliArgs) -> Option<CgroupLimits> {
+    let limits = CgroupLimits {
+        max: args.cgroup_memory_max.clone(),
+        high: args.cgroup_memory_high.clone(),
+        low: args.cgroup_memory_low.clone(),
+        swap_max: args.cgroup_swap_max.clone(),
+        zswap_max: args.cgroup_zswap_max.clone(),
+    };
+    let any_limit = limits.max.is_some()
+        || limits.high.is_some()
+        || limits.low.is_some()
+        || limits.swap_max.is_some()
+        || limits.zswap_max.is_some();
+    (args.cgroup || any_limit).then_some(limits)
+}
+
+fn data_mix(args: &CliArgs) -> DataMix {
+    let rand_data_len: usize = (args.rand_data_percent as usize * PAGE_SIZE) / 100;
+    args.data_profile
+        .clone()
+        .unwrap_or_else(|| DataMix::random_prefix(rand_data_len))
+}
+
+/// Stops the workers of a phase and waits for them.
+fn stop_workers(phase_running: &AtomicBool, handles: &mut Vec<JoinHandle<String>>) {
+    phase_running.store(false, Ordering::SeqCst);
+    join_workers(handles);
+}
+
+fn join_workers(handles: &mut Vec<JoinHandle<String>>) {
+    while let Some(handle) = handles.pop() {
+        let joined = handle.join().expect("Could not join thread");
+        println!("{} joined.", joined);
+    }
+}
+
+/// Tells the running workers to stop without waiting for them, they are
+/// joined by `spawn_retired_workers` once they are done with their hold.
+fn retire_workers(
+    payload: &mut ThreadPayload,
+    handles: &mut Vec<JoinHandle<String>>,
+    retiring: &mut Vec<JoinHandle<String>>,
+) {
+    payload.running.store(false, Ordering::SeqCst);
+    payload.running = Arc::new(AtomicBool::new(true));
+    retiring.append(handles);
+}
+
+/// Joins the retired workers that are done and, once all of them are, spawns
+/// the new ones. True when the new workers were spawned.
+fn spawn_retired_workers(
+    payload: &ThreadPayload,
+    handles: &mut Vec<JoinHandle<String>>,
+    retiring: &mut Vec<JoinHandle<String>>,
+) -> bool {
+    if !handles.is_empty() {
+        return false;
+    }
+    let (mut done, pending): (Vec<_>, Vec<_>) =
+        retiring.drain(..).partition(|handle| handle.is_finished());
+    *retiring = pending;
+    join_workers(&mut done);
+    if !retiring.is_empty() {
+        return false;
+    }
+    *handles = (0..payload.args.threads)
+        .map(|i| spawn_memory_worker(i, payload.clone(format!("worker-{}", i))))
+        .collect();
+    true
+}
+
+/// Stops the running workers and spawns new ones with the current settings
+/// of `payload`.
+fn restart_workers(payload: &mut ThreadPayload, handles: &mut Vec<JoinHandle<String>>) {
+    stop_workers(&payload.running, handles);
+    payload.running = Arc::new(AtomicBool::new(true));
+    *handles = (0..payload.args.threads)
+        .map(|i| spawn_memory_worker(i, payload.clone(format!("worker-{}", i))))
+        .collect();
+}
+
+/// Applies a control request and returns the response for the client.
+fn handle_control(
+    command: ControlCommand,
+    state: &mut State,
+    workers: &mut ThreadPayload,
+    handles: &mut Vec<JoinHandle<String>>,
+    retiring: &mut Vec<JoinHandle<String>>,
+    paused: &AtomicBool,
+) -> String {
+    match command {
+        ControlCommand::Threads(0) => return "error: at least one thread is needed.".to_owned(),
+        ControlCommand::Threads(threads)
+            if (threads as u32) < workers.args.hugetlb_workers as u32 + workers.args.thp_workers as u32 =>
+        {
+            return "error: fewer threads than --hugetlb-workers plus --thp-workers.".to_owned();
+        }
+        ControlCommand::Threads(threads) => workers.args.threads = threads,
+        ControlCommand::ThreadBytes(size) => {
+            if size < PAGE_SIZE {
+                return format!("error: allocations must be at least {} bytes.", PAGE_SIZE);
+            }
+            // Whole pages like at startup, mmap_aligned unmaps the tail at
+            // the end of the allocation.
+            workers.thread_allocation_size = size.next_multiple_of(PAGE_SIZE);
+        }
+        ControlCommand::HoldTimeMs(hold_time_ms) => workers.args.base_hold_time_ms = hold_time_ms,
+        ControlCommand::DataProfile(data) => workers.data = data,
+        ControlCommand::Pause | ControlCommand::Resume => {
+            state.control_paused = matches!(command, ControlCommand::Pause);
+            paused.store(state.paused || state.control_paused, Ordering::SeqCst);
+            return "ok".to_owned();
+        }
+        ControlCommand::Stats => {
+            let sample = match sample_mem_stats(Some(&state.mem_stats), workers.cgroup.as_deref()) {
+                Ok(x) => x,
+                Err(err) => return format!("error: {:#}", err),
+            };
+            let stats = json!({
+                "elapsed_ms": state.start_time.elapsed().as_millis() as u64,
+                "verifications": state.verifications as u64,
+                "phase": state.phase,
+                "threads": workers.args.threads,
+                "thread_bytes": workers.thread_allocation_size as u64,
+                "hold_time_ms": workers.args.base_hold_time_ms,
+                "paused": state.paused || state.control_paused,
+                "stats": sample,
+            });
+            return format!("ok {}", stats);
+        }
+    }
+    // The new workers start from the main loop once the old ones are done.
+    retire_workers(workers, handles, retiring);
+    format!(
+        "ok, restarting with {} threads of {} holding for {}ms",
+        workers.args.threads,
+        fmtb(workers.thread_allocation_size as u128),
+        workers.args.base_hold_time_ms
+    )
+}
+
 fn main() {
     let args = CliArgs::parse();
+    if let Some(Command::Ctl { socket, request }) = &args.command {
+        match send_request(socket, &request.join(" ")) {
+            Ok(response) => {
+                print!("{}", response);
+                std::process::exit(if response.starts_with("ok") { 0 } else { 1 });
+            }
+            Err(err) => {
+                println!("{:#}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(Command::Sweep {
+        param,
+        run_seconds,
+        settle_seconds,
+        output_dir,
+        workload,
+    }) = &args.command
+    {
+        let results = run_sweep(
+            param,
+            workload,
+            *run_seconds,
+            Duration::from_secs(*settle_seconds),
+            output_dir,
+        )
+        .expect("Could not run sweep.");
+        let report = render_sweep(&results);
+        println!("\n{}", report);
+        let path = std::path::Path::new(output_dir).join("sweep-report.txt");
+        if let Err(err) = std::fs::write(&path, &report) {
+            println!("Could not write {}.\n{}", path.display(), err);
+        }
+        let failed = results
+            .iter()
+            .any(|x| x.error.is_some() || x.exit_code != Some(0));
+        std::process::exit(if failed { 1 } else { 0 });
+    }
+    if let Some(Command::Mkswap {
+        path,
+        size,
+        swapon,
+        priority,
+    }) = &args.command
+    {
+        let size = Byte::from_str(size).expect("Invalid swap file size.").get_bytes();
+        create_swapfile(path, size as u64).expect("Could not create swap file.");
+        println!("Swap file {} of {} created.", path, fmtb(size));
+        if *swapon {
+            swap_on(path, *priority).expect("Could not enable swap file.");
+            println!("Swap file {} enabled.", path);
+        }
+        std::process::exit(0);
+    }
+    let phases = match &args.scenario {
+        Some(path) => load_scenario(path, &args).expect("Could not load scenario."),
+        None => {
+            let phase = Phase::whole_run();
+            phase.apply(&args).expect("Invalid arguments.");
+            vec![phase]
+        }
+    };
+    let code = run(&args, &phases).unwrap_or_else(|err| {
+        println!("{:#}", err);
+        FailureKind::Setup.exit_code()
+    });
+    std::process::exit(code);
+}
 
-    let thread_allocation_size =
-        compute_thread_allocation_size(&args).expect("Could not determine allocation size.");
+/// Sets up, runs the phases and returns the exit code. Everything set up is
+/// undone when returning, errors only come from the setup.
+fn run(args: &CliArgs, phases: &[Phase]) -> Result<i32> {
     let running = Arc::new(AtomicBool::new(true));
+    let paused = Arc::new(AtomicBool::new(false));
     let mut state = State {
-        target: Byte::from_bytes((thread_allocation_size as u128) * args.threads as u128),
+        target: Byte::from_bytes(0),
         start_time: Instant::now(),
         mem_stats: MemStats::default(),
-        workers: (0..args.threads).map(|_| WorkerState::Allocating).collect(),
+        workers: Vec::new(),
         verifications: 0,
+        paused: false,
+        control_paused: false,
+        exporter: match &args.output {
+            Some(path) => Some(
+                Exporter::create(path, args.output_format).context("Could not create output file.")?,
+            ),
+            None => None,
+        },
+        peak_swap_used: 0,
+        peak_zswap_pool: 0,
+        peak_zram_used: 0,
+        errors: Vec::new(),
+        failure: None,
+        kernel_events: Vec::new(),
+        phase: None,
+        last_flip: None,
+        last_swap_cycle: None,
+        last_reclaim: None,
+        stats_history: VecDeque::new(),
+    };
+
+    // Set up before the allocation size is computed, the zram swap counts as
+    // available memory.
+    // zram and the cgroup are undone when dropped, the setup steps following
+    // them return their errors instead of panicking.
+    let zram = match &args.zram_size {
+        Some(size) => {
+            let size = Byte::from_str(size)
+                .map_err(|err| anyhow::anyhow!("Invalid zram size {}, {}.", size, err))?
+                .get_bytes();
+            let zram = Zram::setup(
+                size as u64,
+                args.zram_algorithm.as_deref(),
+                args.zram_backing_dev.as_deref(),
+                args.zram_priority,
+            )
+            .context("Could not set up zram.")?;
+            println!("Swapping on {} ({}).", zram.device(), fmtb(size));
+            Some(zram)
+        }
+        None => None,
+    };
+
+    // Before the workers allocate, memory charged earlier stays with the
+    // current cgroup.
+    let cgroup = match cgroup_limits(args) {
+        Some(limits) => {
+            let cgroup = Cgroup::setup(&limits).context("Could not set up the cgroup.")?;
+            println!("Running in {}.", cgroup.path().display());
+            Some(cgroup)
+        }
+        None => None,
+    };
+
+    let control_server = match &args.control_socket {
+        Some(path) => Some(ControlServer::bind(path).context("Could not create control socket.")?),
+        None => None,
+    };
+    let mut flips = args.zswap_flip.clone();
+    let mut flip_seconds = args.zswap_flip_seconds;
+    if let Some(seconds) = args.max_pool_percent_flip_seconds {
+        flips.push(Flip::max_pool_percent().context("Could not set up the max_pool_percent flip.")?);
+        flip_seconds = seconds;
+    }
+    let flipper = match flips.is_empty() {
+        true => None,
+        false => Some(ZswapFlipper::new(flips).context("Could not set up the zswap flipper.")?),
+    };
+    let cycler = match args.swap_cycle.is_empty() {
+        true => None,
+        false => Some(
+            SwapCycler::new(&args.swap_cycle, args.swap_cycle_priorities)
+                .context("Could not set up swap cycling.")?,
+        ),
+    };
+
+    // Last, nothing tears tracing down if a later step fails.
+    let tracer = if args.trace_event.is_empty() {
+        Tracer::find()
+    } else {
+        Some(
+            Tracer::setup(&args.trace_event, args.trace_buffer_kb, &args.trace_filter)
+                .context("Could not set up tracing.")?,
+        )
     };
 
     setup_ctrl(running.clone());
     let (tx, rx): (Sender<Message>, Receiver<Message>) = channel();
 
-    let rand_data_len: usize = (args.rand_data_percent as usize * 4096) / 100;
     let payload = ThreadPayload {
         id: "".to_owned(),
         args: args.clone(),
-        thread_allocation_size: thread_allocation_size,
+        thread_allocation_size: 0,
         running: running.clone(),
+        paused: paused.clone(),
         tx: tx.clone(),
-        rand_data_len: rand_data_len,
+        data: data_mix(args),
+        tracer: tracer.clone(),
+        cgroup: cgroup.as_ref().map(|x| x.path().to_owned()),
     };
 
     let mut join_handles: Vec<JoinHandle<String>> = Vec::new();
 
     join_handles.push(spawn_stats_parser(payload.clone("stats")));
+    join_handles.push(spawn_kmsg_watcher(payload.clone("kmsg")));
+    if let Some(server) = control_server {
+        join_handles.push(spawn_control_server(server, payload.clone("control")));
+    }
+    if let Some(flipper) = flipper {
+        join_handles.push(spawn_zswap_flipper(
+            flipper,
+            Duration::from_secs_f64(flip_seconds),
+            payload.clone("zswap-flipper"),
+        ));
+    }
 
-    for i in 0..args.threads {
-        join_handles.push(spawn_memory_worker(
-            i,
-            payload.clone(format!("worker-{}", i)),
-        ))
+    if let Some(cycler) = cycler {
+        join_handles.push(spawn_swap_cycler(
+            cycler,
+            Duration::from_secs_f64(args.swap_cycle_seconds),
+            payload.clone("swap-cycler"),
+        ));
     }
 
     let rcv_timeout = Duration::from_secs(1);
     let start_time = Instant::now();
     let timeout_secs = args.timeout_seconds.unwrap_or(u64::MAX);
-    while running.load(Ordering::SeqCst) {
-        match rx.recv_timeout(rcv_timeout) {
-            Ok(Message::MemStats(stats)) => {
-                state.mem_stats = stats;
-                render_state(&state);
-            }
-            Ok(Message::WorkerState(worker_id, worker_state)) => {
-                state.workers[worker_id as usize] = worker_state;
-                render_state(&state);
-            }
-            Ok(Message::ThreadError(id, txt)) => {
-                //running.store(false, Ordering::SeqCst);
-                println!("Thread <{}> sent an error.\n{}", id, txt);
-                Command::new("cat")
-                    .arg("/sys/kernel/debug/tracing/trace")
-                    .stdout(std::fs::File::create("/shared/logs.txt").unwrap())
-                    .spawn()
-                    .expect("Failed to cat to logs.txt");
-                break;
+    let progress_interval = Duration::from_secs(args.progress_interval_seconds);
+    let mut last_progress = Instant::now();
+    // The workers of the current phase stop when it ends or when the control
+    // socket changes their settings, the other threads run until the end of
+    // the run.
+    let mut workers = payload.clone("");
+    workers.running = Arc::new(AtomicBool::new(true));
+    let mut worker_handles: Vec<JoinHandle<String>> = Vec::new();
+    // Workers told to stop by a control request, still finishing their hold.
+    let mut retiring_handles: Vec<JoinHandle<String>> = Vec::new();
+    'phases: for (phase_index, phase) in phases.iter().enumerate() {
+        if !running.load(Ordering::SeqCst) {
+            break;
+        }
+        // Checked when loading the scenario, but the available memory might
+        // not be readable anymore.
+        let sized = phase.apply(args).and_then(|phase_args| {
+            let size = compute_thread_allocation_size(&phase_args)?;
+            Ok((phase_args, size))
+        });
+        let (phase_args, thread_allocation_size) = match sized {
+            Ok(x) => x,
+            Err(err) => {
+                let msg = format!("Could not start phase {}.\n{:#}", phase.name, err);
+                println!("{}", msg);
+                state.errors.push(msg);
+                state.failure = Some(FailureKind::Setup);
+                running.store(false, Ordering::SeqCst);
+                break 'phases;
             }
-            Ok(Message::VerificationCompleted) => {
-                state.verifications += 1;
-                render_state(&state);
-                if let Some(target) = args.target {
-                    if target == state.verifications {
+        };
+        state.reset_workers(phase_args.threads, thread_allocation_size);
+        if args.scenario.is_some() {
+            println!(
+                "Phase {} ({}/{}) started, {} threads of {}.",
+                phase.name,
+                phase_index + 1,
+                phases.len(),
+                phase_args.threads,
+                fmtb(thread_allocation_size as u128)
+            );
+            state.phase = Some(format!("{} ({}/{})", phase.name, phase_index + 1, phases.len()));
+            state.record(
+                "phase",
+                &json!({
+                    "name": phase.name,
+                    "index": phase_index,
+                    "threads": phase_args.threads,
+                    "thread_bytes": thread_allocation_size as u64,
+                    "hold_time_ms": phase_args.base_hold_time_ms,
+                }),
+            );
+        }
+        workers.data = data_mix(&phase_args);
+        workers.args = phase_args;
+        workers.thread_allocation_size = thread_allocation_size;
+        join_workers(&mut retiring_handles);
+        restart_workers(&mut workers, &mut worker_handles);
+
+        let phase_start = Instant::now();
+        while running.load(Ordering::SeqCst) {
+            match rx.recv_timeout(rcv_timeout) {
+                Ok(Message::MemStats(stats)) => {
+                    state.record("sample", &stats);
+                    state.mem_stats = *stats;
+                    state.push_history();
+                    state.update_peaks();
+                    let should_pause = psi_should_pause(args, &state.mem_stats.psi, state.paused);
+                    if should_pause != state.paused {
+                        state.record("psi_pause", &json!({ "paused": should_pause }));
+                    }
+                    state.paused = should_pause;
+                    paused.store(state.paused || state.control_paused, Ordering::SeqCst);
+                    render(args, &state);
+                    if let Some(reason) = psi_abort_reason(args, &state.mem_stats.psi) {
+                        println!("{}", reason);
+                        state.record("psi_abort", &json!({ "reason": reason }));
                         running.store(false, Ordering::SeqCst);
-                        break;
+                        break 'phases;
+                    }
+                }
+                Ok(Message::WorkerState(worker_id, worker_state)) => {
+                    state.record(
+                        "worker_state",
+                        &json!({ "worker": { worker_id.to_string(): worker_state } }),
+                    );
+                    // Ignore the last states of the workers of a previous phase.
+                    if let Some(worker) = state.workers.get_mut(worker_id as usize) {
+                        *worker = worker_state;
                     }
+                    render(args, &state);
+                }
+                Ok(Message::ThreadError(id, kind, txt)) => {
+                    //running.store(false, Ordering::SeqCst);
+                    let txt = match &state.last_flip {
+                        Some((time, flip)) => format!(
+                            "{}Last zswap flip {} {:.3}s before.\n",
+                            txt,
+                            flip,
+                            time.elapsed().as_secs_f64()
+                        ),
+                        None => txt,
+                    };
+                    let txt = match &state.last_swap_cycle {
+                        Some((time, cycle)) => format!(
+                            "{}Last swap cycle {} {:.3}s before.\n",
+                            txt,
+                            cycle,
+                            time.elapsed().as_secs_f64()
+                        ),
+                        None => txt,
+                    };
+                    println!("Thread <{}> sent an error.\n{}", id, txt);
+                    state.record(
+                        "error",
+                        &json!({ "thread": id, "kind": kind, "message": txt }),
+                    );
+                    state.flush_output();
+                    state.errors.push(format!("<{}> {}", id, txt));
+                    state.failure.get_or_insert(kind);
+                    // Nobody is looking at the screen in headless mode, don't wait
+                    // for a Ctrl-C.
+                    if args.headless {
+                        running.store(false, Ordering::SeqCst);
+                    }
+                    if let Some(tracer) = &tracer {
+                        match tracer.stop().and_then(|_| tracer.capture(&args.trace_output)) {
+                            Ok(()) => println!("Trace written to {}.", args.trace_output),
+                            Err(err) => println!("Could not capture trace.\n{}", err),
+                        }
+                    }
+                    break 'phases;
+                }
+                Ok(Message::VerificationCompleted) => {
+                    state.verifications += 1;
+                    state.record(
+                        "verification",
+                        &json!({ "verifications": state.verifications as u64 }),
+                    );
+                    render(args, &state);
+                    if let Some(target) = args.target {
+                        if target == state.verifications {
+                            running.store(false, Ordering::SeqCst);
+                            break 'phases;
+                        }
+                    }
+                }
+                Ok(Message::ScrubFailure(id, failure)) => {
+                    let from = failure.from.saturating_duration_since(state.start_time);
+                    let to = failure.to.saturating_duration_since(state.start_time);
+                    println!(
+                        "Thread <{}> found {} bad pages while scrubbing, they went bad between {:.3}s and {:.3}s.",
+                        id,
+                        failure.pages.len(),
+                        from.as_secs_f64(),
+                        to.as_secs_f64()
+                    );
+                    let middle = failure.from + (failure.to - failure.from) / 2;
+                    let sample = state.closest_sample(middle).map(|(time, stats)| {
+                        let at = time.saturating_duration_since(state.start_time);
+                        println!("Closest sample at {:.3}s: {}", at.as_secs_f64(), fmt_sample(stats));
+                        json!({ "elapsed_ms": at.as_millis() as u64, "stats": stats })
+                    });
+                    state.record(
+                        "scrub_failure",
+                        &json!({
+                            "thread": id,
+                            "pages": failure.pages,
+                            "from_ms": from.as_millis() as u64,
+                            "to_ms": to.as_millis() as u64,
+                            "closest_sample": sample,
+                        }),
+                    );
                 }
+                Ok(Message::Control(request, command, reply)) => {
+                    println!("Control request: {}", request);
+                    let response = handle_control(
+                        command,
+                        &mut state,
+                        &mut workers,
+                        &mut worker_handles,
+                        &mut retiring_handles,
+                        &paused,
+                    );
+                    state.record(
+                        "control",
+                        &json!({ "request": request, "response": response }),
+                    );
+                    render(args, &state);
+                    let _ = reply.send(response);
+                }
+                Ok(Message::ZswapFlip(flip)) => {
+                    state.record(
+                        "zswap_flip",
+                        &json!({
+                            "parameter": flip.parameter,
+                            "value": flip.value,
+                            "error": flip.error,
+                        }),
+                    );
+                    let text = match &flip.error {
+                        None => format!("{}={}", flip.parameter, flip.value),
+                        Some(err) => format!("{}={} failed: {}", flip.parameter, flip.value, err),
+                    };
+                    if args.headless {
+                        println!("Zswap flip: {}", text);
+                    }
+                    state.last_flip = Some((Instant::now(), text));
+                    render(args, &state);
+                }
+                Ok(Message::SwapCycle(cycle)) => {
+                    state.record(
+                        "swap_cycle",
+                        &json!({
+                            "device": cycle.device,
+                            "priority": cycle.priority,
+                            "swapoff_ms": cycle.swapoff_ms,
+                            "error": cycle.error,
+                        }),
+                    );
+                    let text = match &cycle.error {
+                        None => format!(
+                            "{} back with priority {}, swapoff took {}ms",
+                            cycle.device, cycle.priority, cycle.swapoff_ms
+                        ),
+                        Some(err) => format!("{} failed: {}", cycle.device, err),
+                    };
+                    if args.headless {
+                        println!("Swap cycle: {}", text);
+                    }
+                    state.last_swap_cycle = Some((Instant::now(), text));
+                    render(args, &state);
+                }
+                Ok(Message::Reclaim(id, reclaim)) => {
+                    state.record(
+                        "reclaim",
+                        &json!({
+                            "thread": id,
+                            "method": reclaim.method,
+                            "residency": reclaim.residency,
+                            "error": reclaim.error,
+                        }),
+                    );
+                    let text = match (&reclaim.residency, &reclaim.error) {
+                        (_, Some(err)) => format!("<{}> failed: {}", id, err),
+                        (Some(residency), None) => {
+                            let percent = |x: usize| match residency.pages {
+                                0 => 0.0,
+                                pages => x as f64 / pages as f64 * 100.0,
+                            };
+                            format!(
+                                "<{}> {:.1}% resident, {:.1}% swapped of {} pages",
+                                id,
+                                percent(residency.resident),
+                                percent(residency.swapped),
+                                residency.pages
+                            )
+                        }
+                        (None, None) => continue,
+                    };
+                    // One per allocation, only the failures are worth a line.
+                    if args.headless && reclaim.error.is_some() {
+                        println!("Reclaim: {}", text);
+                    }
+                    state.last_reclaim = Some(text);
+                }
+                Ok(Message::KernelEvent(event)) => {
+                    state.record("kernel_event", &event);
+                    if args.headless {
+                        println!("Kernel log: [{:?}] {}", event.kind, event.message);
+                    }
+                    let stop = args.stop_on_kernel_warning;
+                    if stop {
+                        state.errors.push(format!("<kmsg> {}", event.message));
+                        state.failure.get_or_insert(FailureKind::Kernel);
+                    }
+                    state.kernel_events.push(event);
+                    render(args, &state);
+                    if stop {
+                        println!("Kernel warning, stopping.");
+                        running.store(false, Ordering::SeqCst);
+                        break 'phases;
+                    }
+                }
+                Err(_) => {}
+            }
+            if spawn_retired_workers(&workers, &mut worker_handles, &mut retiring_handles) {
+                state.reset_workers(workers.args.threads, workers.thread_allocation_size);
+                render(args, &state);
+            }
+            if args.headless && last_progress.elapsed() >= progress_interval {
+                render_progress(&state);
+                last_progress = Instant::now();
+            }
+            if start_time.elapsed().as_secs() >= timeout_secs {
+                running.store(false, Ordering::SeqCst);
+            }
+            if phase.duration().is_some_and(|x| phase_start.elapsed() >= x) {
+                if phase_index + 1 == phases.len() {
+                    running.store(false, Ordering::SeqCst);
+                }
+                break;
             }
-            Err(_) => {}
-        }
-        if start_time.elapsed().as_secs() >= timeout_secs {
-            running.store(false, Ordering::SeqCst);
         }
     }
 
     while running.load(Ordering::SeqCst) {
         sleep(Duration::from_millis(500));
     }
+    state.flush_output();
     println!("Shutting down, waiting for threads to join...");
+    stop_workers(&workers.running, &mut worker_handles);
+    join_workers(&mut retiring_handles);
     while !join_handles.is_empty() {
         let joined = join_handles
             .pop()
@@ -554,5 +1875,27 @@ fn main() {
             .expect("Could not join thread");
         println!("{} joined.", joined);
     }
+    if let Some(tracer) = &tracer {
+        if let Err(err) = tracer.teardown() {
+            println!("Could not disable trace events.\n{}", err);
+        }
+    }
+    drop(cgroup);
+    drop(zram);
+    render_summary(&state);
+    state.record(
+        "summary",
+        &json!({
+            "duration_ms": state.start_time.elapsed().as_millis() as u64,
+            "verifications": state.verifications as u64,
+            "peak_swap_used": state.peak_swap_used as u64,
+            "peak_zswap_pool": state.peak_zswap_pool as u64,
+            "peak_zram_used": state.peak_zram_used as u64,
+            "errors": state.errors,
+            "kernel_events": state.kernel_events.len() as u64,
+        }),
+    );
+    state.flush_output();
     println!("Done.");
+    Ok(state.failure.map(|x| x.exit_code()).unwrap_or(0))
 }
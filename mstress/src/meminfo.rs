@@ -0,0 +1,177 @@
+use crate::{fmtb, print_row};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Default, Clone, Serialize)]
+pub struct FreeStats {
+    pub mem_total: u128,
+    pub mem_free: u128,
+    pub mem_available: u128,
+    pub buffers: u128,
+    pub cached: u128,
+    pub swap_cached: u128,
+    pub swap_total: u128,
+    pub swap_available: u128,
+    pub dirty: u128,
+    pub writeback: u128,
+    pub anon_pages: u128,
+    pub shmem: u128,
+    pub committed_as: u128,
+    // Zswap and Zswapped only exist since 6.4, None on older kernels.
+    pub zswap: Option<u128>,
+    pub zswapped: Option<u128>,
+    pub anon_huge_pages: u128,
+    pub huge_pages_total: u128,
+    pub huge_pages_free: u128,
+    pub huge_pages_rsvd: u128,
+    pub huge_pages_surp: u128,
+    pub huge_page_size: u128,
+}
+
+/// Parses the content of /proc/meminfo into a map of key to value, values
+/// with a kB unit are converted to bytes, the others (e.g. HugePages_*) are
+/// plain counters.
+fn parse_meminfo_entries(txt: &str) -> Result<HashMap<&str, u128>> {
+    let mut out = HashMap::new();
+    for line in txt.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let mut parts = value.split_whitespace();
+        let n = parts
+            .next()
+            .context(format!("Missing value for {} in meminfo.", key))?
+            .parse::<u128>()
+            .context(format!("Invalid value for {} in meminfo.", key))?;
+        let n = match parts.next() {
+            Some("kB") => n * 1024,
+            _ => n,
+        };
+        out.insert(key, n);
+    }
+    Ok(out)
+}
+
+pub fn parse_meminfo() -> Result<FreeStats> {
+    let txt = std::fs::read_to_string("/proc/meminfo").context("Could not read /proc/meminfo.")?;
+    let entries = parse_meminfo_entries(&txt)?;
+    let get = |key: &str| -> Result<u128> {
+        entries
+            .get(key)
+            .copied()
+            .context(format!("Could not find {} in meminfo.", key))
+    };
+    let get_or_zero = |key: &str| entries.get(key).copied().unwrap_or(0);
+
+    Ok(FreeStats {
+        mem_total: get("MemTotal")?,
+        mem_free: get("MemFree")?,
+        mem_available: get("MemAvailable")?,
+        buffers: get_or_zero("Buffers"),
+        cached: get_or_zero("Cached"),
+        swap_cached: get_or_zero("SwapCached"),
+        swap_total: get_or_zero("SwapTotal"),
+        swap_available: get_or_zero("SwapFree"),
+        dirty: get_or_zero("Dirty"),
+        writeback: get_or_zero("Writeback"),
+        anon_pages: get_or_zero("AnonPages"),
+        shmem: get_or_zero("Shmem"),
+        committed_as: get_or_zero("Committed_AS"),
+        zswap: entries.get("Zswap").copied(),
+        zswapped: entries.get("Zswapped").copied(),
+        anon_huge_pages: get_or_zero("AnonHugePages"),
+        huge_pages_total: get_or_zero("HugePages_Total"),
+        huge_pages_free: get_or_zero("HugePages_Free"),
+        huge_pages_rsvd: get_or_zero("HugePages_Rsvd"),
+        huge_pages_surp: get_or_zero("HugePages_Surp"),
+        huge_page_size: get_or_zero("Hugepagesize"),
+    })
+}
+
+fn fmt_opt(bytes: Option<u128>) -> String {
+    bytes.map(fmtb).unwrap_or_else(|| "n/a".to_owned())
+}
+
+pub fn render_free_stats(stats: &FreeStats) {
+    print_row(&["MEMORY", "available", "total"], "<>>");
+    print_row(
+        &["Mem", &fmtb(stats.mem_available), &fmtb(stats.mem_total)],
+        "<>>",
+    );
+    print_row(
+        &["Swap", &fmtb(stats.swap_available), &fmtb(stats.swap_total)],
+        "<>>",
+    );
+    print_row(
+        &[
+            "HugePages",
+            &format!(
+                "{} ({})",
+                stats.huge_pages_free,
+                fmtb(stats.huge_pages_free * stats.huge_page_size)
+            ),
+            &format!(
+                "{} ({})",
+                stats.huge_pages_total,
+                fmtb(stats.huge_pages_total * stats.huge_page_size)
+            ),
+        ],
+        "<>>",
+    );
+    print_row(
+        &[
+            "HugePages rsvd/surp",
+            &stats.huge_pages_rsvd.to_string(),
+            &stats.huge_pages_surp.to_string(),
+        ],
+        "<>>",
+    );
+    print_row(&["free", &fmtb(stats.mem_free), ""], "<>>");
+    print_row(
+        &["buffers/cached", &fmtb(stats.buffers), &fmtb(stats.cached)],
+        "<>>",
+    );
+    print_row(&["swap cached", &fmtb(stats.swap_cached), ""], "<>>");
+    print_row(
+        &[
+            "dirty/writeback",
+            &fmtb(stats.dirty),
+            &fmtb(stats.writeback),
+        ],
+        "<>>",
+    );
+    print_row(
+        &["anon/shmem", &fmtb(stats.anon_pages), &fmtb(stats.shmem)],
+        "<>>",
+    );
+    print_row(&["committed", &fmtb(stats.committed_as), ""], "<>>");
+    print_row(&["anon huge pages", &fmtb(stats.anon_huge_pages), ""], "<>>");
+    print_row(
+        &[
+            "zswap/zswapped",
+            &fmt_opt(stats.zswap),
+            &fmt_opt(stats.zswapped),
+        ],
+        "<>>",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_entries() {
+        let entries = parse_meminfo_entries(
+            "MemTotal:       16384 kB\nSwapFree:           0 kB\nHugePages_Total:       4\n\n",
+        )
+        .unwrap();
+        assert_eq!(entries["MemTotal"], 16384 * 1024);
+        assert_eq!(entries["SwapFree"], 0);
+        assert_eq!(entries["HugePages_Total"], 4);
+        assert_eq!(entries.len(), 3);
+        assert!(parse_meminfo_entries("MemTotal:\n").is_err());
+        assert!(parse_meminfo_entries("MemTotal: lots kB\n").is_err());
+    }
+}
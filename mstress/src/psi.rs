@@ -0,0 +1,215 @@
+use crate::print_row;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::Duration;
+
+const PSI_MEMORY: &str = "/proc/pressure/memory";
+
+#[derive(Default, Clone, Serialize)]
+pub struct PsiLine {
+    pub avg10: f64,
+    pub avg60: f64,
+    pub avg300: f64,
+    // Total stall time in microseconds.
+    pub total: u128,
+}
+
+#[derive(Default, Clone, Serialize)]
+pub struct PsiStats {
+    // False if the kernel was built without CONFIG_PSI or booted with psi=0.
+    pub available: bool,
+    pub some: PsiLine,
+    pub full: PsiLine,
+}
+
+fn parse_psi_line(line: &str) -> Result<PsiLine> {
+    let mut out = PsiLine::default();
+    for part in line.split_whitespace().skip(1) {
+        let (key, value) = part
+            .split_once('=')
+            .context(format!("Invalid psi entry {}.", part))?;
+        match key {
+            "avg10" => out.avg10 = value.parse()?,
+            "avg60" => out.avg60 = value.parse()?,
+            "avg300" => out.avg300 = value.parse()?,
+            "total" => out.total = value.parse()?,
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+pub fn parse_psi() -> Result<PsiStats> {
+    parse_psi_file(Path::new(PSI_MEMORY))
+}
+
+/// Parses a pressure file, e.g. the memory.pressure of a cgroup.
+pub fn parse_psi_file(path: &Path) -> Result<PsiStats> {
+    let txt = match std::fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(_) => return Ok(PsiStats::default()),
+    };
+    let mut stats = PsiStats {
+        available: true,
+        ..Default::default()
+    };
+    for line in txt.lines() {
+        if line.starts_with("some") {
+            stats.some = parse_psi_line(line)?;
+        } else if line.starts_with("full") {
+            stats.full = parse_psi_line(line)?;
+        }
+    }
+    Ok(stats)
+}
+
+/// A PSI trigger registered on /proc/pressure/memory, the kernel wakes up the
+/// poller when the stall time in the window exceeds the threshold.
+pub struct PsiTrigger {
+    file: File,
+}
+
+/// Checks a trigger in the kernel format, "<some|full> <stall us> <window
+/// us>", the window must be between 500ms and 10s and the stall within it.
+pub fn parse_psi_trigger(spec: &str) -> Result<String> {
+    let parts: Vec<&str> = spec.split_whitespace().collect();
+    let times = match parts[..] {
+        ["some" | "full", stall, window] => {
+            stall.parse::<u64>().ok().zip(window.parse::<u64>().ok())
+        }
+        _ => None,
+    };
+    let Some((stall, window)) = times else {
+        bail!(
+            "Invalid psi trigger \"{}\", expected \"<some|full> <stall us> <window us>\".",
+            spec
+        );
+    };
+    if !(500_000..=10_000_000).contains(&window) {
+        bail!("The psi trigger window must be between 500000 and 10000000 us.");
+    }
+    if stall == 0 || stall > window {
+        bail!("The psi trigger stall must be above 0 and at most the window.");
+    }
+    Ok(parts.join(" "))
+}
+
+impl PsiTrigger {
+    /// `spec` uses the kernel format: "<some|full> <stall us> <window us>".
+    pub fn new(spec: &str) -> Result<PsiTrigger> {
+        let spec = parse_psi_trigger(spec)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(PSI_MEMORY)
+            .context(format!("Could not open {}.", PSI_MEMORY))?;
+        // The trigger string must be written in a single write including the
+        // terminating null byte.
+        file.write_all(format!("{}\0", spec).as_bytes())
+            .context(format!("Could not register psi trigger \"{}\".", spec))?;
+        Ok(PsiTrigger { file })
+    }
+
+    /// Waits at most `timeout` for the trigger to fire, returns true if it did.
+    pub fn wait(&self, timeout: Duration) -> Result<bool> {
+        let mut fds = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLPRI,
+            revents: 0,
+        };
+        let n = unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) };
+        if n < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            bail!("Could not poll psi trigger: {}", err);
+        }
+        if fds.revents & libc::POLLERR != 0 {
+            bail!("Psi trigger is gone, the monitored file was removed.");
+        }
+        Ok(fds.revents & libc::POLLPRI != 0)
+    }
+}
+
+pub fn render_psi_stats(stats: &PsiStats) {
+    if !stats.available {
+        print_row(&["PSI", "not available"], "<>");
+        return;
+    }
+    print_row(&["PSI", "some", "full"], "<>>");
+    print_row(
+        &[
+            "avg10",
+            &format!("{:.2}%", stats.some.avg10),
+            &format!("{:.2}%", stats.full.avg10),
+        ],
+        "<>>",
+    );
+    print_row(
+        &[
+            "avg60",
+            &format!("{:.2}%", stats.some.avg60),
+            &format!("{:.2}%", stats.full.avg60),
+        ],
+        "<>>",
+    );
+    print_row(
+        &[
+            "avg300",
+            &format!("{:.2}%", stats.some.avg300),
+            &format!("{:.2}%", stats.full.avg300),
+        ],
+        "<>>",
+    );
+    print_row(
+        &[
+            "total",
+            &format!("{}ms", stats.some.total / 1000),
+            &format!("{}ms", stats.full.total / 1000),
+        ],
+        "<>>",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_line() {
+        let line = parse_psi_line("some avg10=1.50 avg60=0.20 avg300=0.00 total=12345").unwrap();
+        assert_eq!(line.avg10, 1.5);
+        assert_eq!(line.avg60, 0.2);
+        assert_eq!(line.avg300, 0.0);
+        assert_eq!(line.total, 12345);
+        assert!(parse_psi_line("full avg10").is_err());
+        assert!(parse_psi_line("full avg10=abc").is_err());
+    }
+    #[test]
+    fn parse_trigger() {
+        assert_eq!(
+            parse_psi_trigger(" some  150000 1000000 ").unwrap(),
+            "some 150000 1000000"
+        );
+        assert!(parse_psi_trigger("full 500000 500000").is_ok());
+        for spec in [
+            "",
+            "some 150000",
+            "avg 150000 1000000",
+            "some 150ms 1s",
+            "some 150000 1000000 extra",
+            "some 150000 100000",
+            "some 0 1000000",
+            "full 2000000 1000000",
+        ] {
+            assert!(parse_psi_trigger(spec).is_err(), "{}", spec);
+        }
+    }
+}
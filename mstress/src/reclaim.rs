@@ -0,0 +1,174 @@
+use crate::cgroup::current_cgroup;
+use crate::data::PAGE_SIZE;
+use crate::verify::Allocation;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+// From include/uapi/asm-generic/mman-common.h, missing from libc.
+const MADV_COLD: libc::c_int = 20;
+const MADV_PAGEOUT: libc::c_int = 21;
+
+const PM_SWAP: u64 = 1 << 62;
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReclaimMethod {
+    /// Leave it to the kernel.
+    None,
+    /// madvise(MADV_PAGEOUT), reclaims the pages right away.
+    Pageout,
+    /// madvise(MADV_COLD), only moves the pages to the inactive list.
+    Cold,
+    /// process_madvise(MADV_PAGEOUT) on our own pidfd, the path used by
+    /// userspace memory managers.
+    ProcessMadvise,
+    /// Writes the allocation size to memory.reclaim of our cgroup, which
+    /// reclaims from the whole cgroup rather than this allocation.
+    MemoryReclaim,
+}
+
+/// Where the pages of an allocation are after a reclaim.
+#[derive(Clone, Debug, Serialize)]
+pub struct Residency {
+    pub pages: usize,
+    // In memory according to mincore, pages in the swap cache included.
+    pub resident: usize,
+    // Swap entries according to pagemap.
+    pub swapped: usize,
+}
+
+/// The page aligned part of [ptr, ptr + size), malloc'd memory doesn't start
+/// on a page boundary.
+fn page_range(ptr: *mut libc::c_void, size: usize) -> (usize, usize) {
+    let start = (ptr as usize).next_multiple_of(PAGE_SIZE);
+    let end = (ptr as usize + size) / PAGE_SIZE * PAGE_SIZE;
+    (start, end.saturating_sub(start))
+}
+
+fn madvise(start: usize, len: usize, advice: libc::c_int) -> Result<()> {
+    if unsafe { libc::madvise(start as *mut libc::c_void, len, advice) } != 0 {
+        bail!("madvise failed: {}", std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn process_madvise(start: usize, len: usize) -> Result<()> {
+    let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, libc::getpid(), 0) };
+    if pidfd < 0 {
+        bail!("pidfd_open failed: {}", std::io::Error::last_os_error());
+    }
+    let iov = libc::iovec {
+        iov_base: start as *mut libc::c_void,
+        iov_len: len,
+    };
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_process_madvise,
+            pidfd,
+            &iov as *const libc::iovec,
+            1,
+            MADV_PAGEOUT,
+            0,
+        )
+    };
+    let err = std::io::Error::last_os_error();
+    unsafe { libc::close(pidfd as libc::c_int) };
+    if ret < 0 {
+        bail!("process_madvise failed: {}", err);
+    }
+    Ok(())
+}
+
+fn push_out(allocation: &Allocation, method: ReclaimMethod) -> Result<()> {
+    let (start, len) = page_range(allocation.ptr, allocation.size);
+    match method {
+        ReclaimMethod::None => Ok(()),
+        ReclaimMethod::Pageout => madvise(start, len, MADV_PAGEOUT),
+        ReclaimMethod::Cold => madvise(start, len, MADV_COLD),
+        ReclaimMethod::ProcessMadvise => process_madvise(start, len),
+        ReclaimMethod::MemoryReclaim => {
+            let path = current_cgroup()?.join("memory.reclaim");
+            // Not created if missing, e.g. in the root cgroup of older kernels.
+            OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .and_then(|mut file| file.write_all(allocation.size.to_string().as_bytes()))
+                .context(format!("Could not write to {}.", path.display()))
+        }
+    }
+}
+
+/// Counts the pages of the allocation in memory and in swap.
+fn residency(allocation: &Allocation) -> Result<Residency> {
+    let (start, len) = page_range(allocation.ptr, allocation.size);
+    let pages = len / PAGE_SIZE;
+    let mut vec = vec![0u8; pages];
+    if unsafe { libc::mincore(start as *mut libc::c_void, len, vec.as_mut_ptr()) } != 0 {
+        bail!("mincore failed: {}", std::io::Error::last_os_error());
+    }
+    let resident = vec.iter().filter(|x| *x & 1 != 0).count();
+
+    let mut pagemap = File::open("/proc/self/pagemap").context("Could not open pagemap.")?;
+    pagemap.seek(SeekFrom::Start((start / PAGE_SIZE * 8) as u64))?;
+    let mut entries = vec![0u8; pages * 8];
+    pagemap
+        .read_exact(&mut entries)
+        .context("Could not read pagemap.")?;
+    let swapped = entries
+        .chunks_exact(8)
+        .map(|x| u64::from_ne_bytes(x.try_into().unwrap()))
+        .filter(|x| x & PM_SWAP != 0)
+        .count();
+    Ok(Residency {
+        pages,
+        resident,
+        swapped,
+    })
+}
+
+/// The outcome of reclaiming an allocation.
+#[derive(Clone, Serialize)]
+pub struct Reclaim {
+    pub method: ReclaimMethod,
+    pub residency: Option<Residency>,
+    pub error: Option<String>,
+}
+
+/// Pushes the pages of the allocation out of memory and then looks where
+/// they ended up.
+pub fn reclaim(allocation: &Allocation, method: ReclaimMethod) -> Reclaim {
+    let pushed = push_out(allocation, method);
+    let residency = residency(allocation);
+    let error = match (&pushed, &residency) {
+        (Err(err), _) | (_, Err(err)) => Some(format!("{:#}", err)),
+        _ => None,
+    };
+    Reclaim {
+        method,
+        residency: residency.ok(),
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_range_aligns_inward() {
+        let range = |start: usize, size: usize| page_range(start as *mut libc::c_void, size);
+        assert_eq!(range(0x10000, 3 * PAGE_SIZE), (0x10000, 3 * PAGE_SIZE));
+        // malloc'd memory, the partial first and last pages are left out.
+        assert_eq!(range(0x10010, 3 * PAGE_SIZE), (0x11000, 2 * PAGE_SIZE));
+        assert_eq!(
+            range(0x10010, 4 * PAGE_SIZE - 0x10),
+            (0x11000, 3 * PAGE_SIZE)
+        );
+        // Not a single whole page.
+        assert_eq!(range(0x10010, PAGE_SIZE), (0x11000, 0));
+        assert_eq!(range(0x10010, 16), (0x11000, 0));
+    }
+}
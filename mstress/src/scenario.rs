@@ -0,0 +1,229 @@
+use crate::data::parse_data_mix;
+use crate::CliArgs;
+use anyhow::{bail, Context, Result};
+use byte_unit::Byte;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// A size either in bytes or as a string such as "512MiB".
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum ByteSize {
+    Bytes(u64),
+    Text(String),
+}
+
+impl ByteSize {
+    fn bytes(&self) -> Result<u128> {
+        match self {
+            ByteSize::Bytes(x) => Ok(*x as u128),
+            ByteSize::Text(s) => Byte::from_str(s)
+                .map(|x| x.get_bytes())
+                .map_err(|err| anyhow::anyhow!("Invalid size {}, {}.", s, err)),
+        }
+    }
+}
+
+/// One phase of a scenario, anything left out is taken from the command
+/// line.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Phase {
+    pub name: String,
+    threads: Option<u16>,
+    bytes: Option<ByteSize>,
+    hold_time_ms: Option<u64>,
+    data_profile: Option<String>,
+    // Only the last phase can run until the end of the run.
+    duration_seconds: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    phase: Vec<Phase>,
+}
+
+impl Phase {
+    /// The phase of a run without scenario, it lasts as long as the run.
+    pub fn whole_run() -> Phase {
+        Phase {
+            name: "main".to_owned(),
+            threads: None,
+            bytes: None,
+            hold_time_ms: None,
+            data_profile: None,
+            duration_seconds: None,
+        }
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration_seconds.map(Duration::from_secs)
+    }
+
+    /// The command line arguments with the phase settings applied.
+    pub fn apply(&self, args: &CliArgs) -> Result<CliArgs> {
+        let mut args = args.clone();
+        if let Some(threads) = self.threads {
+            args.threads = threads;
+        }
+        if let Some(bytes) = &self.bytes {
+            args.bytes = Some(bytes.bytes()?);
+        }
+        if let Some(hold_time_ms) = self.hold_time_ms {
+            args.base_hold_time_ms = hold_time_ms;
+        }
+        if let Some(profile) = &self.data_profile {
+            args.data_profile = Some(parse_data_mix(profile)?);
+        }
+        if args.threads == 0 {
+            bail!("Phase {} has no threads.", self.name);
+        }
+        if args.hugetlb_workers as u32 + args.thp_workers as u32 > args.threads as u32 {
+            bail!(
+                "Phase {} has {} threads, fewer than --hugetlb-workers plus --thp-workers.",
+                self.name,
+                args.threads
+            );
+        }
+        Ok(args)
+    }
+}
+
+/// Reads the phases of a scenario file, e.g.
+///
+/// ```toml
+/// [[phase]]
+/// name = "ramp-up"
+/// threads = 2
+/// bytes = "256MiB"
+/// duration_seconds = 30
+///
+/// [[phase]]
+/// name = "burst"
+/// threads = 8
+/// hold_time_ms = 50
+/// data_profile = "zero:20,random:80"
+/// ```
+pub fn load_scenario(path: &str, args: &CliArgs) -> Result<Vec<Phase>> {
+    let content =
+        std::fs::read_to_string(path).context(format!("Could not read scenario {}.", path))?;
+    let scenario: Scenario =
+        toml::from_str(&content).context(format!("Could not parse scenario {}.", path))?;
+    if scenario.phase.is_empty() {
+        bail!("Scenario {} has no phases.", path);
+    }
+    for (i, phase) in scenario.phase.iter().enumerate() {
+        if phase.duration_seconds.is_none() && i + 1 < scenario.phase.len() {
+            bail!("Phase {} needs a duration_seconds, only the last phase can omit it.", phase.name);
+        }
+        phase
+            .apply(args)
+            .context(format!("Invalid phase {}.", phase.name))?;
+    }
+    Ok(scenario.phase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn args(extra: &[&str]) -> CliArgs {
+        CliArgs::try_parse_from(["mstress", "--threads", "4"].iter().chain(extra)).unwrap()
+    }
+
+    fn phase(toml: &str) -> Phase {
+        toml::from_str(toml).unwrap()
+    }
+
+    /// Writes the scenario to a file and loads it.
+    fn load(name: &str, toml: &str, args: &CliArgs) -> Result<Vec<Phase>> {
+        let path = std::env::temp_dir().join(format!(
+            "mstress-scenario-{}-{}.toml",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, toml).unwrap();
+        let phases = load_scenario(path.to_str().unwrap(), args);
+        std::fs::remove_file(&path).unwrap();
+        phases
+    }
+
+    #[test]
+    fn apply_phase() {
+        let applied = phase(
+            r#"
+            name = "burst"
+            threads = 8
+            bytes = "1MiB"
+            hold_time_ms = 50
+            data_profile = "zero:20,random:80"
+            "#,
+        )
+        .apply(&args(&[]))
+        .unwrap();
+        assert_eq!(applied.threads, 8);
+        assert_eq!(applied.bytes, Some(1 << 20));
+        assert_eq!(applied.base_hold_time_ms, 50);
+        assert!(applied.data_profile.is_some());
+
+        let applied = phase("name = \"n\"\nbytes = 4096")
+            .apply(&args(&[]))
+            .unwrap();
+        assert_eq!(applied.threads, 4);
+        assert_eq!(applied.bytes, Some(4096));
+    }
+
+    #[test]
+    fn apply_phase_errors() {
+        for toml in [
+            "name = \"n\"\nthreads = 0",
+            "name = \"n\"\nbytes = \"lots\"",
+            "name = \"n\"\ndata_profile = \"zero:10\"",
+            "name = \"n\"\nthreads = 2",
+        ] {
+            let args = args(&["--hugetlb-workers", "2", "--thp-workers", "1"]);
+            assert!(phase(toml).apply(&args).is_err(), "{}", toml);
+        }
+    }
+
+    #[test]
+    fn load_phases() {
+        let phases = load(
+            "ok",
+            r#"
+            [[phase]]
+            name = "ramp-up"
+            threads = 2
+            duration_seconds = 30
+
+            [[phase]]
+            name = "burst"
+            threads = 8
+            "#,
+            &args(&[]),
+        )
+        .unwrap();
+        assert_eq!(phases.len(), 2);
+        assert_eq!(phases[0].name, "ramp-up");
+        assert_eq!(phases[0].duration(), Some(Duration::from_secs(30)));
+        assert_eq!(phases[1].duration(), None);
+    }
+
+    #[test]
+    fn load_errors() {
+        for (name, toml) in [
+            ("empty", "phase = []"),
+            (
+                "duration",
+                "[[phase]]\nname = \"a\"\n[[phase]]\nname = \"b\"",
+            ),
+            ("unknown", "[[phase]]\nname = \"a\"\nthread = 2"),
+            ("invalid", "[[phase]]\nname = \"a\"\nthreads = 0"),
+        ] {
+            assert!(load(name, toml, &args(&[])).is_err(), "{}", name);
+        }
+        assert!(load_scenario("/nonexistent/scenario.toml", &args(&[])).is_err());
+    }
+}
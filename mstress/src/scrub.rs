@@ -0,0 +1,76 @@
+use crate::data::DataMix;
+use crate::verify::Allocation;
+use rand::seq::index::sample;
+use std::time::{Duration, Instant};
+
+/// Pages found bad by a scrub and the window in which they went bad: they
+/// were last seen intact at `from` and found bad at `to`.
+#[derive(Clone, Debug)]
+pub struct ScrubFailure {
+    pub pages: Vec<usize>,
+    pub from: Instant,
+    pub to: Instant,
+}
+
+/// Re-verifies a held allocation, either entirely or a random subset of its
+/// pages on each pass.
+pub struct Scrubber {
+    pub interval: Duration,
+    fraction: f64,
+    allocated_at: Instant,
+    // Time each page was last seen intact, in ms since `allocated_at`.
+    last_good: Vec<u32>,
+}
+
+impl Scrubber {
+    pub fn new(interval: Duration, percent: f64) -> Scrubber {
+        Scrubber {
+            interval,
+            fraction: percent / 100.0,
+            allocated_at: Instant::now(),
+            last_good: Vec::new(),
+        }
+    }
+
+    /// To be called right after a new allocation is filled.
+    pub fn reset(&mut self, allocation: &Allocation) {
+        self.allocated_at = Instant::now();
+        self.last_good = vec![0; allocation.pages()];
+    }
+
+    pub fn scrub(
+        &mut self,
+        allocation: &Allocation,
+        stride: usize,
+        data: &DataMix,
+    ) -> Result<(), ScrubFailure> {
+        let pages = allocation.pages();
+        let amount = ((pages as f64 * self.fraction).ceil() as usize).min(pages);
+        let mut indexes: Vec<usize> = if amount == pages {
+            (0..pages).collect()
+        } else {
+            sample(&mut rand::thread_rng(), pages, amount).into_vec()
+        };
+        indexes.sort_unstable();
+
+        let now = Instant::now();
+        let now_ms = (now - self.allocated_at).as_millis() as u32;
+        let mut bad = Vec::new();
+        for page_index in indexes {
+            if allocation.page_is_intact(page_index, stride, data) {
+                self.last_good[page_index] = now_ms;
+            } else {
+                bad.push(page_index);
+            }
+        }
+        if bad.is_empty() {
+            return Ok(());
+        }
+        let from_ms = bad.iter().map(|i| self.last_good[*i]).min().unwrap_or(0);
+        Err(ScrubFailure {
+            pages: bad,
+            from: self.allocated_at + Duration::from_millis(from_ms as u64),
+            to: now,
+        })
+    }
+}
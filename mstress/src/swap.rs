@@ -0,0 +1,241 @@
+use crate::data::PAGE_SIZE;
+use crate::{fmtb, print_row};
+use anyhow::{bail, Context, Result};
+use rand::Rng;
+use serde::Serialize;
+use std::ffi::CString;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::time::Instant;
+
+const PROC_SWAPS: &str = "/proc/swaps";
+
+// From include/linux/swap.h.
+const SWAP_FLAG_PREFER: libc::c_int = 0x8000;
+const SWAP_FLAG_PRIO_MASK: libc::c_int = 0x7fff;
+const SWAP_MAGIC: &[u8] = b"SWAPSPACE2";
+const MAX_PRIORITY: i32 = 32767;
+
+// Same minimum as mkswap.
+pub const MIN_SWAP_PAGES: u64 = 10;
+
+/// An active swap area as listed in /proc/swaps.
+#[derive(Clone, Debug, Serialize)]
+pub struct SwapDevice {
+    pub name: String,
+    pub kind: String,
+    pub size: u128,
+    pub used: u128,
+    pub priority: i32,
+}
+
+/// Reads /proc/swaps, empty if the kernel has no swap support.
+pub fn parse_swaps() -> Result<Vec<SwapDevice>> {
+    let txt = match std::fs::read_to_string(PROC_SWAPS) {
+        Ok(x) => x,
+        Err(_) => return Ok(Vec::new()),
+    };
+    txt.lines()
+        .skip(1)
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 5 {
+                bail!("Invalid {} line {}.", PROC_SWAPS, line);
+            }
+            Ok(SwapDevice {
+                // Spaces are escaped as \040.
+                name: fields[0].replace("\\040", " "),
+                kind: fields[1].to_owned(),
+                size: fields[2].parse::<u128>()? * 1024,
+                used: fields[3].parse::<u128>()? * 1024,
+                priority: fields[4].parse()?,
+            })
+        })
+        .collect()
+}
+
+pub fn render_swaps(devices: &[SwapDevice]) {
+    if devices.is_empty() {
+        print_row(&["SWAP DEVICES", "none"], "<>");
+        return;
+    }
+    print_row(&["SWAP DEVICES", "used / size", "priority"], "<>>");
+    devices.iter().for_each(|device| {
+        print_row(
+            &[
+                &device.name,
+                &format!("{} / {}", fmtb(device.used), fmtb(device.size)),
+                &device.priority.to_string(),
+            ],
+            "<>>",
+        );
+    });
+}
+
+fn c_path(path: &str) -> Result<CString> {
+    CString::new(path).context(format!("Invalid path {}.", path))
+}
+
+/// Negative priorities can't be asked for, the kernel picks the next one.
+pub fn swap_on(path: &str, priority: Option<i32>) -> Result<()> {
+    let flags = match priority {
+        Some(x) if x >= 0 => SWAP_FLAG_PREFER | (x.min(MAX_PRIORITY) & SWAP_FLAG_PRIO_MASK),
+        _ => 0,
+    };
+    let c_path = c_path(path)?;
+    if unsafe { libc::swapon(c_path.as_ptr(), flags) } != 0 {
+        bail!("swapon {} failed: {}", path, std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn swap_off(path: &str) -> Result<()> {
+    let c_path = c_path(path)?;
+    if unsafe { libc::swapoff(c_path.as_ptr()) } != 0 {
+        bail!("swapoff {} failed: {}", path, std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The first page of a swap area of `pages` pages, the v1 header of
+/// include/linux/swap.h: after 1024 bytes of bootbits come the version,
+/// last_page, nr_badpages, uuid and volume name. The magic ends the page.
+pub fn swap_header(pages: u64) -> Vec<u8> {
+    let mut header = vec![0u8; PAGE_SIZE];
+    header[1024..1028].copy_from_slice(&1u32.to_ne_bytes());
+    header[1028..1032].copy_from_slice(&((pages - 1) as u32).to_ne_bytes());
+    rand::thread_rng().fill(&mut header[1036..1052]);
+    header[PAGE_SIZE - SWAP_MAGIC.len()..].copy_from_slice(SWAP_MAGIC);
+    header
+}
+
+/// Creates a swap file of `size` bytes, rounded down to whole pages. The
+/// file is written entirely since swapon refuses files with holes.
+pub fn create_swapfile(path: &str, size: u64) -> Result<()> {
+    let pages = size / PAGE_SIZE as u64;
+    if pages < MIN_SWAP_PAGES {
+        bail!("A swap file needs at least {} pages.", MIN_SWAP_PAGES);
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .context(format!("Could not create {}.", path))?;
+
+    file.write_all(&swap_header(pages))?;
+
+    let zeros = vec![0u8; 256 * PAGE_SIZE];
+    let mut remaining = (pages as usize - 1) * PAGE_SIZE;
+    while remaining > 0 {
+        let n = remaining.min(zeros.len());
+        file.write_all(&zeros[..n])?;
+        remaining -= n;
+    }
+    file.sync_all()
+        .context(format!("Could not sync {}.", path))?;
+    Ok(())
+}
+
+/// The outcome of one swapoff/swapon cycle.
+pub struct SwapCycle {
+    pub device: String,
+    pub priority: i32,
+    pub swapoff_ms: u64,
+    pub error: Option<String>,
+}
+
+/// Swaps devices off and back on in turn. With `rotate_priorities` the
+/// device comes back above the others, so the preferred device changes every
+/// cycle. `restore` puts back the priorities found at creation.
+pub struct SwapCycler {
+    // Each device with its original priority.
+    devices: Vec<(String, i32)>,
+    rotate_priorities: bool,
+    next: usize,
+}
+
+impl SwapCycler {
+    pub fn new(devices: &[String], rotate_priorities: bool) -> Result<SwapCycler> {
+        let active = parse_swaps()?;
+        let devices = devices
+            .iter()
+            .map(|name| match active.iter().find(|x| &x.name == name) {
+                Some(device) => Ok((name.clone(), device.priority)),
+                None => bail!("{} is not an active swap area.", name),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(SwapCycler {
+            devices,
+            rotate_priorities,
+            next: 0,
+        })
+    }
+
+    fn current_priority(name: &str) -> Option<i32> {
+        parse_swaps()
+            .ok()?
+            .into_iter()
+            .find(|x| x.name == name)
+            .map(|x| x.priority)
+    }
+
+    pub fn cycle(&mut self) -> SwapCycle {
+        let (device, original) = self.devices[self.next % self.devices.len()].clone();
+        self.next += 1;
+        let priority = if self.rotate_priorities {
+            let highest = self
+                .devices
+                .iter()
+                .filter_map(|(name, _)| Self::current_priority(name))
+                .max()
+                .unwrap_or(original);
+            (highest + 1).clamp(0, MAX_PRIORITY)
+        } else {
+            Self::current_priority(&device).unwrap_or(original)
+        };
+        let start = Instant::now();
+        let result = swap_off(&device);
+        let swapoff_ms = start.elapsed().as_millis() as u64;
+        let result = result.and_then(|_| swap_on(&device, Some(priority)));
+        SwapCycle {
+            priority: Self::current_priority(&device).unwrap_or(priority),
+            device,
+            swapoff_ms,
+            error: result.err().map(|err| format!("{:#}", err)),
+        }
+    }
+
+    pub fn restore(&self) -> Result<()> {
+        for (device, priority) in self.devices.iter() {
+            match Self::current_priority(device) {
+                Some(x) if x == *priority || (x < 0 && *priority < 0) => continue,
+                Some(_) => {
+                    swap_off(device)?;
+                    swap_on(device, Some(*priority))?;
+                }
+                None => swap_on(device, Some(*priority))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_layout() {
+        let header = swap_header(256);
+        assert_eq!(header.len(), PAGE_SIZE);
+        assert!(header[..1024].iter().all(|x| *x == 0));
+        assert_eq!(header[1024..1028], 1u32.to_ne_bytes());
+        assert_eq!(header[1028..1032], 255u32.to_ne_bytes());
+        // No bad pages.
+        assert_eq!(header[1032..1036], [0; 4]);
+        assert_eq!(&header[PAGE_SIZE - 10..], b"SWAPSPACE2");
+        assert!(header[1052..PAGE_SIZE - 10].iter().all(|x| *x == 0));
+    }
+}
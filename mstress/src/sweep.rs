@@ -0,0 +1,361 @@
+use crate::flip::{parse_parameter_values, Flip};
+use crate::fmtb;
+use crate::zswap::{read_parameter, write_parameter};
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Parses "<parameter>=<value>[,<value>...]", a single value is allowed.
+pub fn parse_sweep_param(s: &str) -> Result<Flip> {
+    parse_parameter_values(s, 1)
+}
+
+/// Flags the sweep passes to every run, clap refuses them twice.
+const SWEEP_FLAGS: [&str; 6] = [
+    "--headless",
+    "--timeout-seconds",
+    "-t",
+    "--output",
+    "-o",
+    "--output-format",
+];
+
+fn check_workload(workload: &[String]) -> Result<()> {
+    for arg in workload {
+        let flag = SWEEP_FLAGS.iter().find(|flag| {
+            let rest = arg.strip_prefix(**flag);
+            // "-t 5", "-t5", "--output=x".
+            match rest {
+                Some(rest) if flag.starts_with("--") => rest.is_empty() || rest.starts_with('='),
+                Some(_) => true,
+                None => false,
+            }
+        });
+        if let Some(flag) = flag {
+            bail!("The workload can't set {}, the sweep sets it for every run.", flag);
+        }
+    }
+    Ok(())
+}
+
+/// Every combination of the parameter values.
+fn combinations(params: &[Flip]) -> Vec<Vec<(String, String)>> {
+    params.iter().fold(vec![Vec::new()], |combos, flip| {
+        combos
+            .iter()
+            .flat_map(|combo| {
+                flip.values.iter().map(move |value| {
+                    let mut combo = combo.clone();
+                    combo.push((flip.parameter.clone(), value.clone()));
+                    combo
+                })
+            })
+            .collect()
+    })
+}
+
+/// What a run of the workload did, taken from its output file.
+#[derive(Default)]
+pub struct SweepResult {
+    pub config: String,
+    // Why the run could not happen, e.g. a rejected parameter value.
+    pub error: Option<String>,
+    pub exit_code: Option<i32>,
+    pub duration_s: f64,
+    pub verifications: u64,
+    pub peak_pool: u64,
+    pub written_back: i128,
+    pub rejects: i128,
+    // Share of the run spent stalled on memory, from the PSI totals.
+    pub psi_some: f64,
+    pub psi_full: f64,
+    pub verification_errors: u64,
+}
+
+fn sum_deltas(sample: &Value, matches: impl Fn(&str) -> bool) -> i128 {
+    sample["zswap"]["counters"]
+        .as_array()
+        .map(|counters| {
+            counters
+                .iter()
+                .filter(|c| c["name"].as_str().is_some_and(&matches))
+                .filter_map(|c| c["delta"].as_i64())
+                .map(|x| x as i128)
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// Reads the JSON lines output of a run.
+fn summarize(path: &Path) -> Result<SweepResult> {
+    let file = File::open(path).context(format!("Could not open {}.", path.display()))?;
+    let mut result = SweepResult::default();
+    let mut psi_first: Option<(f64, f64)> = None;
+    let mut psi_last = (0.0, 0.0);
+    for line in BufReader::new(file).lines() {
+        let record: Value = serde_json::from_str(&line?)?;
+        let data = &record["data"];
+        match record["kind"].as_str() {
+            Some("sample") => {
+                result.written_back += sum_deltas(data, |name| name == "written_back_pages");
+                result.rejects += sum_deltas(data, |name| name.starts_with("reject_"));
+                let totals = (
+                    data["psi"]["some"]["total"].as_f64().unwrap_or(0.0),
+                    data["psi"]["full"]["total"].as_f64().unwrap_or(0.0),
+                );
+                psi_first.get_or_insert(totals);
+                psi_last = totals;
+            }
+            Some("error") if data["kind"] == "Verification" => result.verification_errors += 1,
+            Some("summary") => {
+                result.duration_s = data["duration_ms"].as_f64().unwrap_or(0.0) / 1000.0;
+                result.verifications = data["verifications"].as_u64().unwrap_or(0);
+                result.peak_pool = data["peak_zswap_pool"].as_u64().unwrap_or(0);
+            }
+            _ => {}
+        }
+    }
+    if let Some(first) = psi_first {
+        if result.duration_s > 0.0 {
+            let run_us = result.duration_s * 1e6;
+            result.psi_some = (psi_last.0 - first.0) / run_us * 100.0;
+            result.psi_full = (psi_last.1 - first.1) / run_us * 100.0;
+        }
+    }
+    Ok(result)
+}
+
+fn run_config(
+    combo: &[(String, String)],
+    workload: &[String],
+    run_seconds: u64,
+    output_dir: &Path,
+    index: usize,
+) -> Result<SweepResult> {
+    for (parameter, value) in combo {
+        write_parameter(parameter, value)?;
+    }
+    let output = output_dir.join(format!("sweep-{}.jsonl", index));
+    let log_path = output_dir.join(format!("sweep-{}.log", index));
+    let log = File::create(&log_path).context(format!("Could not create {}.", log_path.display()))?;
+    let status = Command::new(std::env::current_exe()?)
+        .args(workload)
+        .arg("--headless")
+        .args(["--timeout-seconds", &run_seconds.to_string()])
+        .args(["--output", &output.to_string_lossy()])
+        .args(["--output-format", "jsonl"])
+        .stdout(Stdio::from(log.try_clone()?))
+        .stderr(Stdio::from(log))
+        .status()
+        .context("Could not start the workload.")?;
+    let mut result = summarize(&output)?;
+    result.exit_code = status.code();
+    Ok(result)
+}
+
+/// Runs the workload once per combination of the parameter values, the
+/// parameters are put back to their original values between runs and at the
+/// end. The output and log of every run are kept in `output_dir`.
+///
+/// Nothing else is reset between runs: the counters of a run are deltas over
+/// its own samples, but the pages other processes keep in the zswap pool and
+/// in swap stay there and only `settle` gives the run's own pages time to go.
+pub fn run_sweep(
+    params: &[Flip],
+    workload: &[String],
+    run_seconds: u64,
+    settle: Duration,
+    output_dir: &str,
+) -> Result<Vec<SweepResult>> {
+    check_workload(workload)?;
+    let original = params
+        .iter()
+        .map(|flip| read_parameter(&flip.parameter).map(|value| (flip.parameter.clone(), value)))
+        .collect::<Result<Vec<_>>>()?;
+    let output_dir = PathBuf::from(output_dir);
+    std::fs::create_dir_all(&output_dir)
+        .context(format!("Could not create {}.", output_dir.display()))?;
+
+    // The workload gets the Ctrl-C as well and stops by itself, only stop
+    // sweeping.
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+    ctrlc::set_handler(move || flag.store(true, Ordering::SeqCst))
+        .expect("Could not set Ctrl-C handler.");
+
+    let combos = combinations(params);
+    let mut results = Vec::new();
+    for (index, combo) in combos.iter().enumerate() {
+        let config: Vec<String> = combo.iter().map(|(p, v)| format!("{}={}", p, v)).collect();
+        let config = config.join(" ");
+        println!("[{}/{}] {}", index + 1, combos.len(), config);
+        let result = run_config(combo, workload, run_seconds, &output_dir, index)
+            .unwrap_or_else(|err| SweepResult {
+                error: Some(format!("{:#}", err)),
+                ..Default::default()
+            });
+        results.push(SweepResult { config, ..result });
+
+        original
+            .iter()
+            .try_for_each(|(parameter, value)| write_parameter(parameter, value))
+            .context("Could not restore the zswap parameters.")?;
+        if interrupted.load(Ordering::SeqCst) {
+            println!("Interrupted, skipping the remaining configurations.");
+            break;
+        }
+        if index + 1 < combos.len() {
+            sleep(settle);
+        }
+    }
+    Ok(results)
+}
+
+/// The comparison table of a sweep, one line per configuration.
+pub fn render_sweep(results: &[SweepResult]) -> String {
+    let width = results
+        .iter()
+        .map(|x| x.config.len())
+        .max()
+        .unwrap_or(0)
+        .max("config".len());
+    let mut out = format!(
+        "{:<width$}  {:>9}  {:>12}  {:>12}  {:>9}  {:>8}  {:>8}  {:>6}  {:>4}\n",
+        "config",
+        "verif/s",
+        "peak pool",
+        "written back",
+        "rejects",
+        "psi some",
+        "psi full",
+        "errors",
+        "exit",
+        width = width
+    );
+    for result in results {
+        if let Some(err) = &result.error {
+            out += &format!("{:<width$}  not run: {}\n", result.config, err, width = width);
+            continue;
+        }
+        let throughput = match result.duration_s {
+            x if x > 0.0 => result.verifications as f64 / x,
+            _ => 0.0,
+        };
+        out += &format!(
+            "{:<width$}  {:>9.2}  {:>12}  {:>12}  {:>9}  {:>7.2}%  {:>7.2}%  {:>6}  {:>4}\n",
+            result.config,
+            throughput,
+            fmtb(result.peak_pool as u128),
+            result.written_back,
+            result.rejects,
+            result.psi_some,
+            result.psi_full,
+            result.verification_errors,
+            result
+                .exit_code
+                .map(|x| x.to_string())
+                .unwrap_or("-".to_owned()),
+            width = width
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn flip(parameter: &str, values: &[&str]) -> Flip {
+        Flip {
+            parameter: parameter.to_owned(),
+            values: values.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn every_combination() {
+        let params = [
+            flip("compressor", &["lzo", "zstd"]),
+            flip("max_pool_percent", &["10", "20", "30"]),
+        ];
+        let combos = combinations(&params);
+        assert_eq!(combos.len(), 6);
+        assert_eq!(
+            combos[0],
+            vec![
+                ("compressor".to_owned(), "lzo".to_owned()),
+                ("max_pool_percent".to_owned(), "10".to_owned()),
+            ]
+        );
+        assert_eq!(combos[5][0].1, "zstd");
+        assert_eq!(combos[5][1].1, "30");
+        assert_eq!(combinations(&[]), vec![Vec::new()]);
+    }
+
+    #[test]
+    fn summarize_output() {
+        let sample = |written_back: i64, psi_some: u64, psi_full: u64| {
+            json!({"kind": "sample", "data": {
+                "zswap": {"counters": [
+                    {"name": "written_back_pages", "delta": written_back},
+                    {"name": "reject_compress_poor", "delta": 2},
+                    {"name": "reject_alloc_fail", "delta": 1},
+                    {"name": "pool_limit_hit", "delta": 9},
+                ]},
+                "psi": {"some": {"total": psi_some}, "full": {"total": psi_full}},
+            }})
+        };
+        let records = [
+            sample(5, 1_000_000, 0),
+            json!({"kind": "error", "data": {"kind": "Verification"}}),
+            json!({"kind": "error", "data": {"kind": "Allocation"}}),
+            sample(7, 1_500_000, 100_000),
+            json!({"kind": "summary", "data": {
+                "duration_ms": 10_000,
+                "verifications": 42,
+                "peak_zswap_pool": 4096,
+            }}),
+        ];
+        let path = std::env::temp_dir().join(format!("mstress-sweep-{}.jsonl", std::process::id()));
+        let lines: Vec<String> = records.iter().map(|x| x.to_string()).collect();
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let result = summarize(&path);
+        std::fs::remove_file(&path).unwrap();
+        let result = result.unwrap();
+        assert_eq!(result.written_back, 12);
+        assert_eq!(result.rejects, 6);
+        assert_eq!(result.verification_errors, 1);
+        assert_eq!(result.duration_s, 10.0);
+        assert_eq!(result.verifications, 42);
+        assert_eq!(result.peak_pool, 4096);
+        assert!((result.psi_some - 5.0).abs() < 1e-9);
+        assert!((result.psi_full - 1.0).abs() < 1e-9);
+    }
+    #[test]
+    fn reject_sweep_flags() {
+        let workload =
+            |args: &[&str]| -> Vec<String> { args.iter().map(|x| x.to_string()).collect() };
+        assert!(check_workload(&workload(&["--threads", "4", "--bytes", "1048576"])).is_ok());
+        assert!(check_workload(&workload(&["--output-dir", "x", "--thp-workers", "1"])).is_ok());
+        for args in [
+            &["--headless"][..],
+            &["--timeout-seconds", "5"],
+            &["--timeout-seconds=5"],
+            &["-t", "5"],
+            &["-t5"],
+            &["--output", "x.jsonl"],
+            &["-ox.jsonl"],
+            &["--output-format=json"],
+        ] {
+            assert!(check_workload(&workload(args)).is_err(), "{:?}", args);
+        }
+    }
+}
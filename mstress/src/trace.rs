@@ -0,0 +1,156 @@
+use anyhow::{bail, Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const TRACEFS_DIRS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// Controls the kernel ftrace ring buffer: the tracepoints enabled at startup
+/// are captured to a file when something goes wrong.
+#[derive(Clone, Debug)]
+pub struct Tracer {
+    dir: PathBuf,
+    // Events enabled and filters set by us, reset by `teardown`.
+    events: Vec<String>,
+    filters: Vec<PathBuf>,
+    // Values found before we changed them, put back by `teardown`. A failure
+    // turns tracing off even when `setup` wasn't called.
+    original_buffer_kb: Option<String>,
+    original_tracing_on: Option<String>,
+}
+
+/// Turns "zswap" into "zswap:*", set_event wants a group and an event.
+fn event_spec(event: &str) -> String {
+    let event = event.replace('/', ":");
+    match event.contains(':') {
+        true => event,
+        false => format!("{}:*", event),
+    }
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path)
+        .map(|x| x.trim().to_owned())
+        .context(format!("Could not read {}.", path.display()))
+}
+
+fn write_file(path: &Path, value: &str) -> Result<()> {
+    std::fs::write(path, value).context(format!(
+        "Could not write \"{}\" to {}.",
+        value,
+        path.display()
+    ))
+}
+
+impl Tracer {
+    /// Locates tracefs, None if it is not mounted.
+    pub fn find() -> Option<Tracer> {
+        TRACEFS_DIRS
+            .iter()
+            .map(PathBuf::from)
+            .find(|dir| dir.join("trace").exists())
+            .map(|dir| Tracer {
+                original_tracing_on: read_file(&dir.join("tracing_on")).ok(),
+                dir,
+                events: Vec::new(),
+                filters: Vec::new(),
+                original_buffer_kb: None,
+            })
+    }
+
+    /// Enables the given tracepoints, either a whole group ("vmscan") or a
+    /// single event ("vmscan:mm_vmscan_direct_reclaim_begin"), sets the
+    /// buffer size and the filters ("<event>=<filter>"), then clears the ring
+    /// buffer and turns tracing on.
+    pub fn setup(events: &[String], buffer_kb: Option<u64>, filters: &[String]) -> Result<Tracer> {
+        let Some(mut tracer) = Tracer::find() else {
+            bail!("Could not find tracefs, is it mounted?");
+        };
+        if let Some(kb) = buffer_kb {
+            let path = tracer.dir.join("buffer_size_kb");
+            // Reads e.g. "7 (expanded: 1408)" until the buffer is first used.
+            let original = read_file(&path)?;
+            let original = original.split_whitespace().next().unwrap_or_default();
+            tracer.original_buffer_kb = Some(original.to_owned());
+            write_file(&path, &kb.to_string())?;
+        }
+        for event in events.iter() {
+            let spec = event_spec(event);
+            tracer.enable_event(&spec)?;
+            tracer.events.push(spec);
+        }
+        for filter in filters.iter() {
+            let Some((event, expr)) = filter.split_once('=') else {
+                bail!(
+                    "Invalid trace filter {}, expected <event>=<filter>.",
+                    filter
+                );
+            };
+            let path = event_spec(event)
+                .trim_end_matches(":*")
+                .split(':')
+                .fold(tracer.dir.join("events"), |path, part| path.join(part))
+                .join("filter");
+            write_file(&path, expr)?;
+            tracer.filters.push(path);
+        }
+        // Opening trace for writing with O_TRUNC clears the ring buffer.
+        File::create(tracer.dir.join("trace")).context("Could not clear the trace buffer.")?;
+        write_file(&tracer.dir.join("tracing_on"), "1")?;
+        Ok(tracer)
+    }
+
+    fn enable_event(&self, spec: &str) -> Result<()> {
+        let path = self.dir.join("set_event");
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .context(format!("Could not open {}.", path.display()))?;
+        file.write_all(spec.as_bytes())
+            .context(format!("Unknown trace event {}.", spec))
+    }
+
+    /// Stops writing to the ring buffer so that the events leading to a
+    /// failure are not overwritten.
+    pub fn stop(&self) -> Result<()> {
+        write_file(&self.dir.join("tracing_on"), "0")
+    }
+
+    /// Copies the content of the ring buffer to `path`.
+    pub fn capture<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::copy(self.dir.join("trace"), path)
+            .context(format!("Could not write trace to {}.", path.display()))?;
+        Ok(())
+    }
+
+    /// Disables the events, clears the filters and puts back the buffer size
+    /// set by `setup` and tracing_on as `find` saw it.
+    pub fn teardown(&self) -> Result<()> {
+        for spec in self.events.iter() {
+            self.enable_event(&format!("!{}", spec))?;
+        }
+        for path in self.filters.iter() {
+            write_file(path, "0")?;
+        }
+        if let Some(kb) = &self.original_buffer_kb {
+            write_file(&self.dir.join("buffer_size_kb"), kb)?;
+        }
+        if let Some(on) = &self.original_tracing_on {
+            write_file(&self.dir.join("tracing_on"), on)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_specs() {
+        assert_eq!(event_spec("zswap"), "zswap:*");
+        assert_eq!(event_spec("zswap/zswap_store"), "zswap:zswap_store");
+        assert_eq!(event_spec("kmem:mm_page_alloc"), "kmem:mm_page_alloc");
+    }
+}
@@ -0,0 +1,736 @@
+use crate::alloc::{allocate, release, AllocBackend};
+use crate::data::{DataMix, PAGE_SIZE};
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::fmt;
+use xxhash_rust::xxh3::xxh3_64;
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VerifyMode {
+    /// Check the 8 bytes markers written every --stride bytes.
+    Markers,
+    /// Check a checksum of every page taken at allocation time, on top of the
+    /// markers. Every bad page is reported.
+    Checksum,
+}
+
+const MARKER_MAGIC: u8 = 0x11;
+
+/// Identifies who wrote a marker: the worker and which of its allocations.
+/// Only the low byte of the generation fits in the marker.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MarkerTag {
+    pub worker: u16,
+    pub generation: u64,
+}
+
+/// A worker allocation, the memory is not released on drop, only by a
+/// successful `verify_and_free`, so that a corrupted allocation is left around
+/// for inspection.
+pub struct Allocation {
+    pub ptr: *mut libc::c_void,
+    pub size: usize,
+    pub backend: AllocBackend,
+    pub tag: MarkerTag,
+    // The content of every page is generated from this seed so that the
+    // expected content can be rebuilt when reporting a corruption.
+    pub seed: u64,
+    // Per page checksums, only in checksum mode.
+    checksums: Vec<u64>,
+    // Generation of every page, only once some pages have been rewritten.
+    generations: Vec<u64>,
+}
+
+impl Allocation {
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.size) }
+    }
+
+    /// The tag of the markers of the page at `page_index`, its generation
+    /// changes every time the page is rewritten.
+    fn page_tag(&self, page_index: usize) -> MarkerTag {
+        MarkerTag {
+            worker: self.tag.worker,
+            generation: self
+                .generations
+                .get(page_index)
+                .copied()
+                .unwrap_or(self.tag.generation),
+        }
+    }
+
+    /// Seed of the content of a page, rewritten pages get new content.
+    fn page_seed(&self, page_index: usize) -> u64 {
+        let rewrites = self
+            .page_tag(page_index)
+            .generation
+            .wrapping_sub(self.tag.generation);
+        self.seed ^ rewrites.wrapping_mul(0xd1b54a32d192ed03)
+    }
+
+    /// The marker at `index` as it should read, a marker straddling two
+    /// pages takes the generation of each page for its bytes in that page.
+    fn expected_marker(&self, index: usize, stride: usize) -> [u8; 8] {
+        let offset = index * stride;
+        let first = self.page_tag(offset / PAGE_SIZE);
+        let last = self.page_tag((offset + 7) / PAGE_SIZE);
+        let mut bytes = marker(first, index);
+        if last != first {
+            let split = PAGE_SIZE - offset % PAGE_SIZE;
+            bytes[split..].copy_from_slice(&marker(last, index)[split..]);
+        }
+        bytes
+    }
+
+    /// Rebuilds what the page at `page_index` is supposed to contain.
+    pub fn expected_page(&self, page_index: usize, stride: usize, data: &DataMix) -> Vec<u8> {
+        let start = page_index * PAGE_SIZE;
+        let end = (start + PAGE_SIZE).min(self.size);
+        let mut page = vec![0u8; end - start];
+        data.fill_page(
+            page_index,
+            &mut page,
+            &mut page_rng(self.page_seed(page_index), page_index),
+        );
+        let tag = self.page_tag(page_index);
+        // Markers overlapping the page, the first one might start in the
+        // previous page.
+        let mut index = start.saturating_sub(7).div_ceil(stride);
+        while index * stride < end && index * stride < self.size - 8 {
+            let offset = index * stride;
+            if has_marker(data, offset) {
+                for (i, byte) in marker(tag, index).iter().enumerate() {
+                    if (start..end).contains(&(offset + i)) {
+                        page[offset + i - start] = *byte;
+                    }
+                }
+            }
+            index += 1;
+        }
+        page
+    }
+
+    pub fn pages(&self) -> usize {
+        self.size.div_ceil(PAGE_SIZE)
+    }
+
+    /// Rewrites `percent` of the pages, picked at random, with new content
+    /// and markers of `generation`. Every page is read before being written
+    /// so that a swapped out page is first swapped in clean and then dirtied
+    /// in the swap cache. Returns the number of pages rewritten.
+    pub fn rewrite(
+        &mut self,
+        percent: f64,
+        generation: u64,
+        stride: usize,
+        data: &DataMix,
+    ) -> usize {
+        let pages = self.pages();
+        let amount = ((pages as f64 * percent / 100.0).ceil() as usize).min(pages);
+        if amount == 0 {
+            return 0;
+        }
+        if self.generations.is_empty() {
+            self.generations = vec![self.tag.generation; pages];
+        }
+        for page_index in sample(&mut rand::thread_rng(), pages, amount) {
+            let start = page_index * PAGE_SIZE;
+            unsafe { std::ptr::read_volatile((self.ptr as *const u8).add(start)) };
+            self.generations[page_index] = generation;
+            let page = self.expected_page(page_index, stride, data);
+            let slice: &mut [u8] =
+                unsafe { std::slice::from_raw_parts_mut(self.ptr as *mut u8, self.size) };
+            slice[start..start + page.len()].copy_from_slice(&page);
+            if let Some(checksum) = self.checksums.get_mut(page_index) {
+                *checksum = xxh3_64(&page);
+            }
+        }
+        amount
+    }
+
+    /// True if the page still holds what was written at allocation time,
+    /// checked against its checksum when there is one.
+    pub fn page_is_intact(&self, page_index: usize, stride: usize, data: &DataMix) -> bool {
+        let start = page_index * PAGE_SIZE;
+        let actual = &self.as_slice()[start..(start + PAGE_SIZE).min(self.size)];
+        match self.checksums.get(page_index) {
+            Some(checksum) => xxh3_64(actual) == *checksum,
+            None => self.expected_page(page_index, stride, data) == actual,
+        }
+    }
+
+    /// Looks at the page holding the bad byte at `offset` and tells what kind
+    /// of corruption it is.
+    pub fn classify(&self, offset: usize, stride: usize, data: &DataMix) -> Corruption {
+        let slice = self.as_slice();
+        // A bad marker can straddle two pages, pick the one actually damaged.
+        let pages = [
+            offset / PAGE_SIZE,
+            (offset + 7).min(self.size - 1) / PAGE_SIZE,
+        ];
+        let Some((page_index, expected)) = pages
+            .iter()
+            .map(|i| (*i, self.expected_page(*i, stride, data)))
+            .find(|(i, expected)| {
+                expected[..] != slice[i * PAGE_SIZE..i * PAGE_SIZE + expected.len()]
+            })
+        else {
+            return Corruption::Transient;
+        };
+        let start = page_index * PAGE_SIZE;
+        let actual = &slice[start..start + expected.len()];
+
+        let diff: Vec<usize> = (0..actual.len())
+            .filter(|i| actual[*i] != expected[*i])
+            .collect();
+        if diff.len() == 1 && (actual[diff[0]] ^ expected[diff[0]]).count_ones() == 1 {
+            return Corruption::BitFlip {
+                offset: start + diff[0],
+                expected: expected[diff[0]],
+                actual: actual[diff[0]],
+            };
+        }
+        if actual.iter().all(|x| *x == 0) {
+            return Corruption::ZeroedPage;
+        }
+
+        // Look for markers that are not ours in the overwritten bytes. A
+        // marker found at position p of a page must have an index that puts
+        // it at p, which rules out most random matches.
+        for p in 0..actual.len().saturating_sub(7) {
+            let bytes = &actual[p..p + 8];
+            if bytes == &expected[p..p + 8] {
+                continue;
+            }
+            let Some((worker, generation, index)) = decode_marker(bytes) else {
+                continue;
+            };
+            if (index * stride) % PAGE_SIZE != p {
+                continue;
+            }
+            let page = index * stride / PAGE_SIZE;
+            if worker != self.tag.worker {
+                return Corruption::OtherWorker {
+                    worker,
+                    generation,
+                    index,
+                };
+            }
+            if generation != self.page_tag(page).generation as u8 {
+                return Corruption::Stale {
+                    generation,
+                    index,
+                    page,
+                };
+            }
+            if page != page_index {
+                return Corruption::Misplaced { index, page };
+            }
+        }
+        Corruption::Garbage {
+            bad_bytes: diff.len(),
+        }
+    }
+}
+
+/// What a corrupted page looks like compared to what it should contain.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Corruption {
+    /// A single bit differs in the whole page.
+    BitFlip {
+        offset: usize,
+        expected: u8,
+        actual: u8,
+    },
+    /// The page only contains zeros.
+    ZeroedPage,
+    /// The page holds the content of another page of the same allocation.
+    Misplaced { index: usize, page: usize },
+    /// The page holds content written by a previous allocation of the same
+    /// worker, e.g. stale swap data.
+    Stale {
+        generation: u8,
+        index: usize,
+        page: usize,
+    },
+    /// The page holds content written by another worker.
+    OtherWorker {
+        worker: u16,
+        generation: u8,
+        index: usize,
+    },
+    /// Nothing recognizable.
+    Garbage { bad_bytes: usize },
+    /// The page reads correctly on a second look.
+    Transient,
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Corruption::BitFlip {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "single bit flip at {:#x}, {:#04x} became {:#04x}",
+                offset, expected, actual
+            ),
+            Corruption::ZeroedPage => write!(f, "zeroed page"),
+            Corruption::Misplaced { index, page } => write!(
+                f,
+                "misplaced page, holds marker {} of page {}",
+                index, page
+            ),
+            Corruption::Stale {
+                generation,
+                index,
+                page,
+            } => write!(
+                f,
+                "stale page from generation {} (mod 256) of this worker, holds marker {} of page {}",
+                generation, index, page
+            ),
+            Corruption::OtherWorker {
+                worker,
+                generation,
+                index,
+            } => write!(
+                f,
+                "page of worker-{} generation {} (mod 256), holds marker {}",
+                worker, generation, index
+            ),
+            Corruption::Garbage { bad_bytes } => {
+                write!(f, "garbage, {} bytes differ", bad_bytes)
+            }
+            Corruption::Transient => write!(f, "not reproducible, the page now reads correctly"),
+        }
+    }
+}
+
+/// The failure of `verify_and_free`, the allocation is handed back untouched.
+pub struct VerifyFailure {
+    pub allocation: Allocation,
+    pub message: String,
+    // Offset of the first bad byte found in each bad page.
+    pub bad_offsets: Vec<usize>,
+    // Kind of corruption of each bad page.
+    pub corruptions: Vec<Corruption>,
+}
+
+/// Description of a verification failure and the offsets of the bad bytes.
+type Mismatch = (String, Vec<usize>);
+
+fn page_rng(seed: u64, page_index: usize) -> StdRng {
+    StdRng::seed_from_u64(seed ^ (page_index as u64).wrapping_mul(0x9e3779b97f4a7c15))
+}
+
+fn marker(tag: MarkerTag, index: usize) -> [u8; 8] {
+    [
+        MARKER_MAGIC,
+        (tag.worker >> 8) as u8,
+        (tag.worker & 0xff) as u8,
+        tag.generation as u8,
+        ((index >> 24) & 0xff) as u8,
+        ((index >> 16) & 0xff) as u8,
+        ((index >> 8) & 0xff) as u8,
+        (index & 0xff) as u8,
+    ]
+}
+
+/// Returns the worker, generation and index of a marker.
+fn decode_marker(bytes: &[u8]) -> Option<(u16, u8, usize)> {
+    if bytes[0] != MARKER_MAGIC {
+        return None;
+    }
+    // Reject runs of the magic byte, same-filled pages can hold those.
+    if bytes.iter().all(|x| *x == MARKER_MAGIC) {
+        return None;
+    }
+    let worker = u16::from_be_bytes([bytes[1], bytes[2]]);
+    let index = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+    Some((worker, bytes[3], index))
+}
+
+/// True if the 8 bytes marker at `offset` lies in pages that are not filled
+/// with a single byte.
+fn has_marker(data: &DataMix, offset: usize) -> bool {
+    data.fill_byte(offset / PAGE_SIZE).is_none()
+        && data.fill_byte((offset + 7) / PAGE_SIZE).is_none()
+}
+
+pub fn make_allocation(
+    size: usize,
+    stride: usize,
+    data: &DataMix,
+    backend: AllocBackend,
+    mode: VerifyMode,
+    tag: MarkerTag,
+) -> Option<Allocation> {
+    let ptr = allocate(size, backend);
+    if ptr.is_null() {
+        return None;
+    }
+    let slice: &mut [u8] = unsafe { std::slice::from_raw_parts_mut(ptr as *mut u8, size) };
+    let seed: u64 = rand::thread_rng().gen();
+    slice
+        .chunks_mut(PAGE_SIZE)
+        .enumerate()
+        .for_each(|(page_index, page)| {
+            data.fill_page(page_index, page, &mut page_rng(seed, page_index))
+        });
+
+    let mut i = 0;
+    let mut index = 0;
+    while i < size - 8 {
+        if has_marker(data, i) {
+            slice[i..i + 8].copy_from_slice(&marker(tag, index));
+        }
+        i += stride;
+        index += 1;
+    }
+
+    let checksums = match mode {
+        VerifyMode::Markers => Vec::new(),
+        VerifyMode::Checksum => slice.chunks(PAGE_SIZE).map(xxh3_64).collect(),
+    };
+    Some(Allocation {
+        ptr,
+        size,
+        backend,
+        tag,
+        seed,
+        checksums,
+        generations: Vec::new(),
+    })
+}
+
+/// Checks that the pages of the zero and same-filled profiles are still
+/// entirely filled with their byte.
+fn verify_fill_pages(slice: &[u8], data: &DataMix) -> Result<(), Mismatch> {
+    for (page_index, page) in slice.chunks(PAGE_SIZE).enumerate() {
+        let Some(expected) = data.fill_byte(page_index) else {
+            continue;
+        };
+        if let Some(offset) = page.iter().position(|x| *x != expected) {
+            let start = offset - offset % 8;
+            let end = (start + 8).min(page.len());
+            let msg = format!(
+                "Possible memory corruption at {:p} ({:#x}), page {} should be filled with {:#04x}.\n{:x?} <--- THE BAD GUY\n",
+                &page[offset],
+                page_index * PAGE_SIZE + offset,
+                page_index,
+                expected,
+                &page[start..end]
+            );
+            return Err((msg, vec![page_index * PAGE_SIZE + offset]));
+        }
+    }
+    Ok(())
+}
+
+fn verify_markers(allocation: &Allocation, stride: usize, data: &DataMix) -> Result<(), Mismatch> {
+    let slice = allocation.as_slice();
+    let size = slice.len();
+    let mut i = 0;
+    let mut index = 0;
+    let mut ring: VecDeque<[u8; 8]> = VecDeque::new();
+    for _ in 0..4 {
+        ring.push_back([0u8; 8]);
+    }
+    while i < size - 8 {
+        if !has_marker(data, i) {
+            i += stride;
+            index += 1;
+            continue;
+        }
+        let failed = slice[i..i + 8] != allocation.expected_marker(index, stride);
+        let mut popped = ring.pop_front().unwrap();
+        popped.clone_from_slice(&slice[i..i + 8]);
+        ring.push_back(popped);
+        if failed {
+            let mut msg = String::new();
+            msg += &format!("Possible memory corruption at {:p} ({:#x}).", &slice[i], i);
+            while !ring.is_empty() {
+                let popped = ring.pop_front().unwrap();
+                msg += &format!("\n{:x?}", popped);
+            }
+            msg += " <--- THE BAD GUY\n";
+            return Err((msg, vec![i]));
+        }
+        i += stride;
+        index += 1;
+    }
+    Ok(())
+}
+
+/// Hashes every page and compares it with the checksum taken at allocation
+/// time, all the bad pages are reported instead of stopping at the first one.
+fn verify_checksums(slice: &[u8], checksums: &[u64]) -> Result<(), Mismatch> {
+    let bad: Vec<(usize, String)> = slice
+        .chunks(PAGE_SIZE)
+        .zip(checksums.iter())
+        .enumerate()
+        .filter_map(|(page_index, (page, expected))| {
+            let actual = xxh3_64(page);
+            if actual == *expected {
+                return None;
+            }
+            let line = format!(
+                "page {} at {:p} ({:#x}): expected {:016x} got {:016x}",
+                page_index,
+                page.as_ptr(),
+                page_index * PAGE_SIZE,
+                expected,
+                actual
+            );
+            Some((page_index * PAGE_SIZE, line))
+        })
+        .collect();
+    if !bad.is_empty() {
+        let lines: Vec<&str> = bad.iter().map(|(_, line)| line.as_str()).collect();
+        let msg = format!(
+            "Checksum mismatch on {} of {} pages.\n{}\n",
+            bad.len(),
+            checksums.len(),
+            lines.join("\n")
+        );
+        return Err((msg, bad.into_iter().map(|(offset, _)| offset).collect()));
+    }
+    Ok(())
+}
+
+pub fn verify_and_free(
+    allocation: Allocation,
+    stride: usize,
+    data: &DataMix,
+) -> Result<(), Box<VerifyFailure>> {
+    let slice = allocation.as_slice();
+    let result = if allocation.checksums.is_empty() {
+        Ok(())
+    } else {
+        verify_checksums(slice, &allocation.checksums)
+    };
+    let result = result
+        .and_then(|_| verify_fill_pages(slice, data))
+        .and_then(|_| verify_markers(&allocation, stride, data));
+    match result {
+        Ok(()) => {
+            release(allocation.ptr, allocation.size, allocation.backend);
+            Ok(())
+        }
+        Err((mut message, bad_offsets)) => {
+            let corruptions: Vec<Corruption> = bad_offsets
+                .iter()
+                .map(|offset| allocation.classify(*offset, stride, data))
+                .collect();
+            message += "Corruption types:\n";
+            bad_offsets
+                .iter()
+                .zip(corruptions.iter())
+                .for_each(|(offset, corruption)| {
+                    message += &format!("page {}: {}\n", offset / PAGE_SIZE, corruption)
+                });
+            Err(Box::new(VerifyFailure {
+                allocation,
+                message,
+                bad_offsets,
+                corruptions,
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::parse_data_mix;
+
+    const PAGES: usize = 8;
+    const STRIDE: usize = 64;
+
+    fn new_allocation(
+        data: &DataMix,
+        mode: VerifyMode,
+        worker: u16,
+        generation: u64,
+    ) -> Allocation {
+        make_allocation(
+            PAGES * PAGE_SIZE,
+            STRIDE,
+            data,
+            AllocBackend::Mmap,
+            mode,
+            MarkerTag { worker, generation },
+        )
+        .unwrap()
+    }
+
+    fn slice_mut(allocation: &mut Allocation) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(allocation.ptr as *mut u8, allocation.size) }
+    }
+
+    fn free(allocation: Allocation) {
+        release(allocation.ptr, allocation.size, allocation.backend);
+    }
+
+    #[test]
+    fn decode_marker_round_trip() {
+        let tag = MarkerTag {
+            worker: 0x1234,
+            generation: 0x1ff,
+        };
+        assert_eq!(
+            decode_marker(&marker(tag, 0x01020304)),
+            Some((0x1234, 0xff, 0x01020304))
+        );
+        assert_eq!(decode_marker(&[MARKER_MAGIC; 8]), None);
+        assert_eq!(decode_marker(&[0, 0, 1, 2, 0, 0, 0, 3]), None);
+    }
+
+    #[test]
+    fn expected_page_matches_allocation() {
+        let size = PAGES * PAGE_SIZE;
+        let mixes = [
+            parse_data_mix("zero:25,same-filled:25,random:25,ratio=4:25").unwrap(),
+            DataMix::random_prefix(0),
+        ];
+        for backend in [AllocBackend::Mmap, AllocBackend::Malloc] {
+            for data in mixes.iter() {
+                // Leave garbage behind for malloc to hand back.
+                let dirty = allocate(size, backend);
+                unsafe { std::ptr::write_bytes(dirty as *mut u8, 0xa5, size) };
+                release(dirty, size, backend);
+
+                let tag = MarkerTag {
+                    worker: 1,
+                    generation: 1,
+                };
+                let allocation =
+                    make_allocation(size, STRIDE, data, backend, VerifyMode::Markers, tag).unwrap();
+                for page_index in 0..allocation.pages() {
+                    let start = page_index * PAGE_SIZE;
+                    assert_eq!(
+                        allocation.expected_page(page_index, STRIDE, data),
+                        allocation.as_slice()[start..start + PAGE_SIZE],
+                        "{:?} page {}",
+                        backend,
+                        page_index
+                    );
+                    assert!(allocation.page_is_intact(page_index, STRIDE, data));
+                }
+                assert!(verify_and_free(allocation, STRIDE, data).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn rewrite_round_trip() {
+        let data = parse_data_mix("text:50,random:50").unwrap();
+        let mut allocation = new_allocation(&data, VerifyMode::Checksum, 1, 1);
+        assert_eq!(allocation.rewrite(50.0, 2, STRIDE, &data), PAGES / 2);
+        assert_eq!(allocation.rewrite(0.0, 3, STRIDE, &data), 0);
+        let rewritten = (0..PAGES)
+            .filter(|i| allocation.page_tag(*i).generation == 2)
+            .count();
+        assert_eq!(rewritten, PAGES / 2);
+        for page_index in 0..PAGES {
+            assert!(allocation.page_is_intact(page_index, STRIDE, &data));
+            let start = page_index * PAGE_SIZE;
+            assert_eq!(
+                allocation.expected_page(page_index, STRIDE, &data),
+                allocation.as_slice()[start..start + PAGE_SIZE]
+            );
+        }
+        assert!(verify_and_free(allocation, STRIDE, &data).is_ok());
+    }
+
+    #[test]
+    fn classify_bit_flip_and_zeroed_page() {
+        let data = parse_data_mix("random").unwrap();
+        let mut allocation = new_allocation(&data, VerifyMode::Checksum, 1, 1);
+        assert_eq!(
+            allocation.classify(PAGE_SIZE, STRIDE, &data),
+            Corruption::Transient
+        );
+
+        let offset = 3 * PAGE_SIZE + 100;
+        let expected = allocation.as_slice()[offset];
+        slice_mut(&mut allocation)[offset] ^= 0x10;
+        assert_eq!(
+            allocation.classify(offset, STRIDE, &data),
+            Corruption::BitFlip {
+                offset,
+                expected,
+                actual: expected ^ 0x10,
+            }
+        );
+
+        slice_mut(&mut allocation)[5 * PAGE_SIZE..6 * PAGE_SIZE].fill(0);
+        assert_eq!(
+            allocation.classify(5 * PAGE_SIZE, STRIDE, &data),
+            Corruption::ZeroedPage
+        );
+
+        slice_mut(&mut allocation)[6 * PAGE_SIZE..7 * PAGE_SIZE].fill(0x55);
+        assert!(matches!(
+            allocation.classify(6 * PAGE_SIZE, STRIDE, &data),
+            Corruption::Garbage { .. }
+        ));
+        assert!(
+            verify_and_free(allocation, STRIDE, &data).is_err_and(|failure| {
+                free(failure.allocation);
+                failure.corruptions.len() == 3
+            })
+        );
+    }
+
+    #[test]
+    fn classify_foreign_content() {
+        let data = parse_data_mix("random").unwrap();
+        let mut allocation = new_allocation(&data, VerifyMode::Markers, 1, 6);
+        let previous = new_allocation(&data, VerifyMode::Markers, 1, 5);
+        let other = new_allocation(&data, VerifyMode::Markers, 2, 9);
+        let page =
+            |a: &Allocation, i: usize| a.as_slice()[i * PAGE_SIZE..(i + 1) * PAGE_SIZE].to_vec();
+
+        let misplaced = page(&allocation, 1);
+        slice_mut(&mut allocation)[3 * PAGE_SIZE..4 * PAGE_SIZE].copy_from_slice(&misplaced);
+        assert_eq!(
+            allocation.classify(3 * PAGE_SIZE, STRIDE, &data),
+            Corruption::Misplaced {
+                index: PAGE_SIZE / STRIDE,
+                page: 1,
+            }
+        );
+
+        slice_mut(&mut allocation)[4 * PAGE_SIZE..5 * PAGE_SIZE]
+            .copy_from_slice(&page(&previous, 4));
+        assert_eq!(
+            allocation.classify(4 * PAGE_SIZE, STRIDE, &data),
+            Corruption::Stale {
+                generation: 5,
+                index: 4 * PAGE_SIZE / STRIDE,
+                page: 4,
+            }
+        );
+
+        slice_mut(&mut allocation)[5 * PAGE_SIZE..6 * PAGE_SIZE].copy_from_slice(&page(&other, 5));
+        assert_eq!(
+            allocation.classify(5 * PAGE_SIZE, STRIDE, &data),
+            Corruption::OtherWorker {
+                worker: 2,
+                generation: 9,
+                index: 5 * PAGE_SIZE / STRIDE,
+            }
+        );
+
+        free(allocation);
+        free(previous);
+        free(other);
+    }
+}
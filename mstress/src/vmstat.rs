@@ -0,0 +1,142 @@
+use crate::print_row;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Counters sampled from /proc/vmstat, in display order.
+const TRACKED_COUNTERS: [&str; 21] = [
+    "pswpin",
+    "pswpout",
+    "pgmajfault",
+    "pgscan_kswapd",
+    "pgscan_direct",
+    "pgsteal_kswapd",
+    "pgsteal_direct",
+    "workingset_refault",
+    "compact_stall",
+    "zswpin",
+    "zswpout",
+    "zswpwb",
+    "oom_kill",
+    "thp_fault_alloc",
+    "thp_fault_fallback",
+    "thp_collapse_alloc",
+    "thp_split_page",
+    "thp_split_pmd",
+    "thp_deferred_split_page",
+    "thp_swpout",
+    "thp_swpout_fallback",
+];
+
+#[derive(Clone, Serialize)]
+pub struct VmstatCounter {
+    pub name: &'static str,
+    pub total: Option<u128>,
+    // Events per second since the previous sample.
+    pub rate: Option<f64>,
+}
+
+#[derive(Default, Clone, Serialize)]
+pub struct VmstatStats {
+    #[serde(skip)]
+    pub sampled_at: Option<Instant>,
+    pub counters: Vec<VmstatCounter>,
+}
+
+impl VmstatStats {
+    pub fn get(&self, name: &str) -> Option<u128> {
+        self.counters
+            .iter()
+            .find(|c| c.name == name)
+            .and_then(|c| c.total)
+    }
+}
+
+fn parse_vmstat_entries(txt: &str) -> HashMap<&str, u128> {
+    let mut out: HashMap<&str, u128> = txt
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            Some((key, value.trim().parse::<u128>().ok()?))
+        })
+        .collect();
+    // Since 5.9 the refaults are split between anon and file.
+    if !out.contains_key("workingset_refault") {
+        let anon = out.get("workingset_refault_anon").copied();
+        let file = out.get("workingset_refault_file").copied();
+        if anon.is_some() || file.is_some() {
+            out.insert("workingset_refault", anon.unwrap_or(0) + file.unwrap_or(0));
+        }
+    }
+    out
+}
+
+/// Samples /proc/vmstat, `prev` is the previous sample and is used to compute
+/// the per second rates.
+pub fn parse_vmstat(prev: Option<&VmstatStats>) -> Result<VmstatStats> {
+    let txt = std::fs::read_to_string("/proc/vmstat").context("Could not read /proc/vmstat.")?;
+    let now = Instant::now();
+    let entries = parse_vmstat_entries(&txt);
+    let elapsed = prev
+        .and_then(|p| p.sampled_at)
+        .map(|t| now.duration_since(t).as_secs_f64())
+        .filter(|x| *x > 0.0);
+
+    let counters = TRACKED_COUNTERS
+        .iter()
+        .map(|name| {
+            let total = entries.get(name).copied();
+            let prev_total = prev.and_then(|p| p.get(name));
+            let rate = match (total, prev_total, elapsed) {
+                (Some(a), Some(b), Some(secs)) => Some(a.saturating_sub(b) as f64 / secs),
+                _ => None,
+            };
+            VmstatCounter { name, total, rate }
+        })
+        .collect();
+
+    Ok(VmstatStats {
+        sampled_at: Some(now),
+        counters,
+    })
+}
+
+pub fn render_vmstat_stats(stats: &VmstatStats) {
+    print_row(&["VMSTAT", "total", "per second"], "<>>");
+    stats.counters.iter().for_each(|counter| {
+        let total = match counter.total {
+            Some(x) => x.to_string(),
+            None => "absent".to_owned(),
+        };
+        let rate = match counter.rate {
+            Some(x) => format!("{:.1}", x),
+            None => "-".to_owned(),
+        };
+        print_row(&[counter.name, &total, &rate], "<>>");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_entries() {
+        let entries = parse_vmstat_entries(
+            "pswpin 10\npswpout 20\nworkingset_refault_anon 3\nworkingset_refault_file 4\nbroken\nnr_bad x\n",
+        );
+        assert_eq!(entries["pswpin"], 10);
+        assert_eq!(entries["pswpout"], 20);
+        assert_eq!(entries["workingset_refault"], 7);
+        assert!(!entries.contains_key("nr_bad"));
+        assert_eq!(entries.len(), 5);
+    }
+
+    #[test]
+    fn keep_unsplit_refaults() {
+        let entries = parse_vmstat_entries("workingset_refault 5\nworkingset_refault_anon 3\n");
+        assert_eq!(entries["workingset_refault"], 5);
+        assert!(!parse_vmstat_entries("pswpin 1\n").contains_key("workingset_refault"));
+    }
+}
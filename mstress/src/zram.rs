@@ -0,0 +1,284 @@
+use crate::data::PAGE_SIZE;
+use crate::swap::{swap_header, swap_off, swap_on, MIN_SWAP_PAGES};
+use crate::{fmtb, print_row};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const SYS_BLOCK: &str = "/sys/block";
+const ZRAM_CONTROL: &str = "/sys/class/zram-control";
+
+// Columns of mm_stat and io_stat, see Documentation/admin-guide/blockdev/zram.rst.
+// Older kernels have fewer of them.
+const MM_STAT_FIELDS: [&str; 9] = [
+    "orig_data_size",
+    "compr_data_size",
+    "mem_used_total",
+    "mem_limit",
+    "mem_used_max",
+    "same_pages",
+    "pages_compacted",
+    "huge_pages",
+    "huge_pages_since",
+];
+const IO_STAT_FIELDS: [&str; 4] = ["failed_reads", "failed_writes", "invalid_io", "notify_free"];
+const BD_STAT_FIELDS: [&str; 3] = ["bd_count", "bd_reads", "bd_writes"];
+
+#[derive(Clone, Serialize)]
+pub struct ZramCounter {
+    pub name: String,
+    pub total: u128,
+}
+
+/// The stats of an initialized zram device.
+#[derive(Clone, Serialize)]
+pub struct ZramStats {
+    pub name: String,
+    pub disksize: u128,
+    pub algorithm: String,
+    pub counters: Vec<ZramCounter>,
+}
+
+impl ZramStats {
+    pub fn get(&self, name: &str) -> Option<u128> {
+        self.counters
+            .iter()
+            .find(|c| c.name == name)
+            .map(|c| c.total)
+    }
+}
+
+fn read_attr(dir: &Path, name: &str) -> Option<String> {
+    std::fs::read_to_string(dir.join(name))
+        .ok()
+        .map(|x| x.trim().to_owned())
+}
+
+fn write_attr(dir: &Path, name: &str, value: &str) -> Result<()> {
+    let path = dir.join(name);
+    std::fs::write(&path, value).context(format!(
+        "Could not write \"{}\" to {}.",
+        value,
+        path.display()
+    ))
+}
+
+/// Zips the columns of a stat file with their names.
+fn parse_columns(txt: Option<String>, names: &[&str]) -> Vec<ZramCounter> {
+    let Some(txt) = txt else {
+        return Vec::new();
+    };
+    names
+        .iter()
+        .zip(txt.split_whitespace())
+        .filter_map(|(name, value)| {
+            Some(ZramCounter {
+                name: name.to_string(),
+                total: value.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// The algorithm in use is the bracketed one, e.g. "lzo [lzo-rle] zstd".
+fn current_algorithm(txt: &str) -> String {
+    txt.split_whitespace()
+        .find(|x| x.starts_with('['))
+        .map(|x| x.trim_matches(|c| c == '[' || c == ']').to_owned())
+        .unwrap_or_default()
+}
+
+/// Reads the stats of every initialized zram device.
+pub fn parse_zram() -> Vec<ZramStats> {
+    let mut names: Vec<String> = std::fs::read_dir(SYS_BLOCK)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|name| name.starts_with("zram"))
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let dir = Path::new(SYS_BLOCK).join(&name);
+            let disksize = read_attr(&dir, "disksize")?.parse::<u128>().ok()?;
+            if disksize == 0 {
+                return None;
+            }
+            let mut counters = parse_columns(read_attr(&dir, "mm_stat"), &MM_STAT_FIELDS);
+            counters.extend(parse_columns(read_attr(&dir, "io_stat"), &IO_STAT_FIELDS));
+            counters.extend(parse_columns(read_attr(&dir, "bd_stat"), &BD_STAT_FIELDS));
+            Some(ZramStats {
+                name,
+                disksize,
+                algorithm: current_algorithm(&read_attr(&dir, "comp_algorithm").unwrap_or_default()),
+                counters,
+            })
+        })
+        .collect()
+}
+
+pub fn render_zram_stats(devices: &[ZramStats]) {
+    devices.iter().for_each(|device| {
+        print_row(
+            &[
+                &format!("ZRAM {}", device.name),
+                &device.algorithm,
+                &fmtb(device.disksize),
+            ],
+            "<>>",
+        );
+        if let (Some(orig), Some(compr)) = (
+            device.get("orig_data_size"),
+            device.get("compr_data_size"),
+        ) {
+            let ratio = match compr {
+                0 => 0.0,
+                x => orig as f64 / x as f64,
+            };
+            print_row(&["compression ratio", &format!("{:.2}", ratio)], "<>");
+        }
+        device.counters.iter().for_each(|counter| {
+            let value = match counter.name.as_str() {
+                "orig_data_size" | "compr_data_size" | "mem_used_total" | "mem_limit"
+                | "mem_used_max" => fmtb(counter.total),
+                _ => counter.total.to_string(),
+            };
+            print_row(&[&counter.name, &value], "<>");
+        });
+    });
+}
+
+/// A zram device set up as swap by us, swapped off and reset when dropped.
+pub struct Zram {
+    dir: PathBuf,
+    device: String,
+    index: u32,
+    // Only devices we hot added are removed.
+    hot_added: bool,
+}
+
+impl Zram {
+    /// Finds an unused zram device, hot adding one if possible.
+    fn find_device() -> Result<(u32, bool)> {
+        let control = Path::new(ZRAM_CONTROL);
+        if let Some(index) = read_attr(control, "hot_add") {
+            return Ok((index.parse()?, true));
+        }
+        let dir = Path::new(SYS_BLOCK).join("zram0");
+        match read_attr(&dir, "disksize").as_deref() {
+            Some("0") => Ok((0, false)),
+            Some(_) => bail!("zram0 is already in use and zram-control is not available."),
+            None => bail!("No zram device, is the zram module loaded?"),
+        }
+    }
+
+    /// Sets up a zram device of `disksize` bytes and enables it as swap.
+    pub fn setup(
+        disksize: u64,
+        algorithm: Option<&str>,
+        backing_dev: Option<&str>,
+        priority: i32,
+    ) -> Result<Zram> {
+        if disksize / (PAGE_SIZE as u64) < MIN_SWAP_PAGES {
+            bail!("A zram swap device needs at least {} pages.", MIN_SWAP_PAGES);
+        }
+        let (index, hot_added) = Self::find_device()?;
+        let zram = Zram {
+            dir: Path::new(SYS_BLOCK).join(format!("zram{}", index)),
+            device: format!("/dev/zram{}", index),
+            index,
+            hot_added,
+        };
+        // Dropping it undoes what was done so far if a step fails.
+        zram.configure(disksize, algorithm, backing_dev, priority)?;
+        Ok(zram)
+    }
+
+    fn configure(
+        &self,
+        disksize: u64,
+        algorithm: Option<&str>,
+        backing_dev: Option<&str>,
+        priority: i32,
+    ) -> Result<()> {
+        // The algorithm and the backing device must be set before the size.
+        if let Some(algorithm) = algorithm {
+            write_attr(&self.dir, "comp_algorithm", algorithm)?;
+        }
+        if let Some(backing_dev) = backing_dev {
+            write_attr(&self.dir, "backing_dev", backing_dev)?;
+        }
+        write_attr(&self.dir, "disksize", &disksize.to_string())?;
+        let mut device = OpenOptions::new()
+            .write(true)
+            .open(&self.device)
+            .context(format!("Could not open {}.", self.device))?;
+        device.write_all(&swap_header(disksize / PAGE_SIZE as u64))?;
+        device.sync_all()?;
+        swap_on(&self.device, Some(priority))
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    fn teardown(&self) -> Result<()> {
+        // Not active if the setup failed before swapon.
+        let _ = swap_off(&self.device);
+        write_attr(&self.dir, "reset", "1")?;
+        if self.hot_added {
+            write_attr(Path::new(ZRAM_CONTROL), "hot_remove", &self.index.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Zram {
+    fn drop(&mut self) {
+        if let Err(err) = self.teardown() {
+            println!("Could not tear down {}.\n{}", self.device, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_stat_columns() {
+        let counters = parse_columns(Some("4096 1024 8192 0\n".to_owned()), &MM_STAT_FIELDS);
+        let counters: Vec<(&str, u128)> = counters
+            .iter()
+            .map(|c| (c.name.as_str(), c.total))
+            .collect();
+        assert_eq!(
+            counters,
+            vec![
+                ("orig_data_size", 4096),
+                ("compr_data_size", 1024),
+                ("mem_used_total", 8192),
+                ("mem_limit", 0),
+            ]
+        );
+        // Extra columns of a newer kernel are ignored.
+        assert_eq!(
+            parse_columns(Some("1 2 3 4 5".to_owned()), &BD_STAT_FIELDS).len(),
+            3
+        );
+        assert!(parse_columns(None, &IO_STAT_FIELDS).is_empty());
+    }
+
+    #[test]
+    fn bracketed_algorithm() {
+        assert_eq!(current_algorithm("lzo [lzo-rle] lz4 zstd\n"), "lzo-rle");
+        assert_eq!(current_algorithm("[zstd]"), "zstd");
+        assert_eq!(current_algorithm("lzo zstd"), "");
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{fmtb, print_row};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+
+const ZSWAP_DEBUGFS: &str = "/sys/kernel/debug/zswap";
+const ZSWAP_PARAMETERS: &str = "/sys/module/zswap/parameters";
+
+/// Counters we know about, in display order. Not every kernel exposes all of
+/// them (e.g. reject_compress_fail and decompress_fail are newer than 6.3),
+/// the missing ones are reported as absent.
+const KNOWN_COUNTERS: [&str; 12] = [
+    "pool_total_size",
+    "stored_pages",
+    "same_filled_pages",
+    "written_back_pages",
+    "pool_limit_hit",
+    "reject_reclaim_fail",
+    "reject_alloc_fail",
+    "reject_kmemcache_fail",
+    "reject_compress_fail",
+    "reject_compress_poor",
+    "duplicate_entry",
+    "decompress_fail",
+];
+
+#[derive(Clone, Serialize)]
+pub struct ZswapCounter {
+    pub name: String,
+    pub total: Option<u128>,
+    // Change since the previous sample, some counters (e.g. stored_pages) are
+    // gauges and can go down.
+    pub delta: Option<i128>,
+}
+
+#[derive(Default, Clone, Serialize)]
+pub struct ZswapStats {
+    pub available: bool,
+    pub counters: Vec<ZswapCounter>,
+}
+
+impl ZswapStats {
+    pub fn get(&self, name: &str) -> Option<u128> {
+        self.counters
+            .iter()
+            .find(|c| c.name == name)
+            .and_then(|c| c.total)
+    }
+}
+
+/// Reads a zswap module parameter, e.g. "max_pool_percent".
+pub fn read_parameter(name: &str) -> Result<String> {
+    let path = Path::new(ZSWAP_PARAMETERS).join(name);
+    let value = std::fs::read_to_string(&path)
+        .context(format!("Could not read {}.", path.display()))?;
+    Ok(value.trim().to_owned())
+}
+
+pub fn write_parameter(name: &str, value: &str) -> Result<()> {
+    let path = Path::new(ZSWAP_PARAMETERS).join(name);
+    std::fs::write(&path, value).context(format!(
+        "Could not write \"{}\" to {}.",
+        value,
+        path.display()
+    ))
+}
+
+fn read_counter(dir: &Path, name: &str) -> Option<u128> {
+    let txt = std::fs::read_to_string(dir.join(name)).ok()?;
+    txt.trim().parse::<u128>().ok()
+}
+
+/// Reads every counter in the zswap debugfs directory, `prev` is the previous
+/// sample and is used to compute the per interval deltas.
+pub fn parse_zswap(prev: Option<&ZswapStats>) -> ZswapStats {
+    let dir = Path::new(ZSWAP_DEBUGFS);
+    if !dir.is_dir() {
+        return ZswapStats::default();
+    }
+
+    let mut names: Vec<String> = KNOWN_COUNTERS.iter().map(|x| x.to_string()).collect();
+    let mut extra: Vec<String> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|name| !KNOWN_COUNTERS.contains(&name.as_str()))
+                .collect()
+        })
+        .unwrap_or_default();
+    extra.sort();
+    names.append(&mut extra);
+
+    let counters = names
+        .into_iter()
+        .map(|name| {
+            let total = read_counter(dir, &name);
+            let prev_total = prev.and_then(|p| p.get(&name));
+            let delta = match (total, prev_total) {
+                (Some(a), Some(b)) => Some(a as i128 - b as i128),
+                _ => None,
+            };
+            ZswapCounter { name, total, delta }
+        })
+        .collect();
+
+    ZswapStats {
+        available: true,
+        counters,
+    }
+}
+
+fn fmt_delta(delta: Option<i128>) -> String {
+    match delta {
+        Some(x) if x > 0 => format!("+{}", x),
+        Some(x) => x.to_string(),
+        None => "-".to_owned(),
+    }
+}
+
+pub fn render_zswap_stats(stats: &ZswapStats) {
+    if !stats.available {
+        print_row(&["ZSWAP", "debugfs not available"], "<>");
+        return;
+    }
+    print_row(&["ZSWAP", "total", "interval"], "<>>");
+    if let Some(size) = stats.get("pool_total_size") {
+        print_row(&["pool size", &fmtb(size), ""], "<>>");
+    }
+    if let Some(pages) = stats.get("stored_pages") {
+        print_row(&["stored", &fmtb(pages * 4096), ""], "<>>");
+    }
+    stats.counters.iter().for_each(|counter| {
+        let total = match counter.total {
+            Some(x) => x.to_string(),
+            None => "absent".to_owned(),
+        };
+        print_row(&[&counter.name, &total, &fmt_delta(counter.delta)], "<>>");
+    });
+}